@@ -0,0 +1,281 @@
+//! Configuration of the reth internals backing a [`RethMiddleware`].
+
+use crate::{
+    chain::ChainSource,
+    fallback::{Backend, Fallback, FallbackPolicy, ServedByObserver},
+    init::InitError,
+    RethMiddleware,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ethers::providers::Middleware;
+use thiserror::Error;
+
+use reth_blockchain_tree::BlockchainTreeConfig;
+use reth_primitives::{constants::ETHEREUM_BLOCK_GAS_LIMIT, ChainSpec};
+use reth_rpc::eth::{cache::EthStateCacheConfig, gas_oracle::GasPriceOracleConfig};
+use tokio::runtime::Handle;
+
+/// The default number of traces that may be computed concurrently.
+pub const DEFAULT_MAX_TRACING_REQUESTS: u32 = 10;
+
+/// The default maximum number of logs returned by a single log query.
+pub const DEFAULT_MAX_LOGS_PER_RESPONSE: usize = 1000;
+
+/// The default maximum number of blocks a single trace filter may span.
+pub const DEFAULT_MAX_TRACE_FILTER_BLOCKS: u64 = 100;
+
+/// The default gas cap of `eth_call` and `eth_estimateGas`.
+pub const DEFAULT_GAS_CAP: u64 = ETHEREUM_BLOCK_GAS_LIMIT;
+
+/// A configuration rejected by [`RethMiddlewareBuilder::build`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A limit was set to zero, which would reject every request.
+    #[error("{0} must be greater than zero")]
+    Zero(&'static str),
+
+    /// The gas price percentile is not a percentile.
+    #[error("gas price percentile must be at most 100, got {0}")]
+    InvalidPercentile(u32),
+}
+
+/// Builds a [`RethMiddleware`], exposing the limits of the reth components it spawns.
+///
+/// ```no_run
+/// # use ethers::providers::{Http, Provider};
+/// # use ethers_reth::builder::RethMiddlewareBuilder;
+/// # fn example(
+/// #     provider: Provider<Http>,
+/// #     handle: tokio::runtime::Handle,
+/// # ) -> Result<(), ethers_reth::init::InitError> {
+/// let middleware = RethMiddlewareBuilder::new("/path/to/reth/db", handle, 1)
+///     .max_tracing_requests(100)
+///     .max_logs_per_response(100_000)
+///     .build(provider)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RethMiddlewareBuilder {
+    pub(crate) db_path: PathBuf,
+    pub(crate) handle: Handle,
+    pub(crate) chain: ChainSource,
+    pub(crate) registered_chains: Vec<Arc<ChainSpec>>,
+    pub(crate) state_cache_config: EthStateCacheConfig,
+    pub(crate) gas_oracle_config: GasPriceOracleConfig,
+    pub(crate) max_tracing_requests: u32,
+    pub(crate) max_logs_per_response: usize,
+    pub(crate) max_trace_filter_blocks: u64,
+    pub(crate) gas_cap: u64,
+    pub(crate) tree_config: BlockchainTreeConfig,
+    pub(crate) strict_open: bool,
+    pub(crate) fallback_policy: FallbackPolicy,
+    pub(crate) served_by_observer: Option<ServedByObserver>,
+}
+
+impl RethMiddlewareBuilder {
+    /// Creates a builder for the database at `db_path`, with reth's defaults for every limit.
+    ///
+    /// The chain is given as a chain id of a chain known to reth, as a [`ChainSpec`] for any
+    /// other chain, or detected from the database, see [`ChainSource`].
+    pub fn new<P: AsRef<Path>, C: Into<ChainSource>>(db_path: P, handle: Handle, chain: C) -> Self {
+        Self {
+            db_path: db_path.as_ref().to_path_buf(),
+            handle,
+            chain: chain.into(),
+            registered_chains: Vec::new(),
+            state_cache_config: EthStateCacheConfig::default(),
+            gas_oracle_config: GasPriceOracleConfig::default(),
+            max_tracing_requests: DEFAULT_MAX_TRACING_REQUESTS,
+            max_logs_per_response: DEFAULT_MAX_LOGS_PER_RESPONSE,
+            max_trace_filter_blocks: DEFAULT_MAX_TRACE_FILTER_BLOCKS,
+            gas_cap: DEFAULT_GAS_CAP,
+            tree_config: BlockchainTreeConfig::default(),
+            strict_open: true,
+            fallback_policy: FallbackPolicy::disabled(),
+            served_by_observer: None,
+        }
+    }
+
+    /// Registers a chain spec that [`ChainSource::Id`] and [`ChainSource::Detect`] can select, in
+    /// addition to the chains known to reth.
+    pub fn register_chain_spec(mut self, chain_spec: Arc<ChainSpec>) -> Self {
+        self.registered_chains.push(chain_spec);
+        self
+    }
+
+    /// Sets the sizes of the block, receipt and EVM environment caches.
+    pub fn state_cache_config(mut self, config: EthStateCacheConfig) -> Self {
+        self.state_cache_config = config;
+        self
+    }
+
+    /// Sets how gas prices are suggested.
+    pub fn gas_oracle_config(mut self, config: GasPriceOracleConfig) -> Self {
+        self.gas_oracle_config = config;
+        self
+    }
+
+    /// Sets how many traces may be computed concurrently.
+    pub fn max_tracing_requests(mut self, max_tracing_requests: u32) -> Self {
+        self.max_tracing_requests = max_tracing_requests;
+        self
+    }
+
+    /// Sets the maximum number of logs a single log query may return.
+    pub fn max_logs_per_response(mut self, max_logs_per_response: usize) -> Self {
+        self.max_logs_per_response = max_logs_per_response;
+        self
+    }
+
+    /// Sets the maximum number of blocks a single trace filter may span, as every block of the
+    /// range is traced.
+    pub fn max_trace_filter_blocks(mut self, max_trace_filter_blocks: u64) -> Self {
+        self.max_trace_filter_blocks = max_trace_filter_blocks;
+        self
+    }
+
+    /// Sets the gas limit of `eth_call` and `eth_estimateGas`.
+    pub fn gas_cap(mut self, gas_cap: u64) -> Self {
+        self.gas_cap = gas_cap;
+        self
+    }
+
+    /// Sets the configuration of the blockchain tree.
+    pub fn tree_config(mut self, config: BlockchainTreeConfig) -> Self {
+        self.tree_config = config;
+        self
+    }
+
+    /// Sets whether the database directory is checked for the MDBX data file and the reth
+    /// database version before it is opened. Enabled by default.
+    pub fn strict_open(mut self, strict_open: bool) -> Self {
+        self.strict_open = strict_open;
+        self
+    }
+
+    /// Sets for which missing local data requests are retried against the inner middleware.
+    /// Disabled by default.
+    pub fn fallback_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback_policy = policy;
+        self
+    }
+
+    /// Sets a callback that is told which backend served each request subject to the fallback
+    /// policy.
+    pub fn on_served<F>(mut self, observer: F) -> Self
+    where
+        F: Fn(&'static str, Backend) + Send + Sync + 'static,
+    {
+        self.served_by_observer = Some(ServedByObserver::new(observer));
+        self
+    }
+
+    /// Checks that every limit admits at least one request.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let cache = &self.state_cache_config;
+        if cache.max_blocks == 0 {
+            return Err(ConfigError::Zero("max cached blocks"))
+        }
+        if cache.max_receipts == 0 {
+            return Err(ConfigError::Zero("max cached receipts"))
+        }
+        if cache.max_envs == 0 {
+            return Err(ConfigError::Zero("max cached EVM environments"))
+        }
+
+        let oracle = &self.gas_oracle_config;
+        if oracle.blocks == 0 {
+            return Err(ConfigError::Zero("gas price oracle blocks"))
+        }
+        if oracle.percentile > 100 {
+            return Err(ConfigError::InvalidPercentile(oracle.percentile))
+        }
+
+        if self.max_tracing_requests == 0 {
+            return Err(ConfigError::Zero("max tracing requests"))
+        }
+        if self.max_logs_per_response == 0 {
+            return Err(ConfigError::Zero("max logs per response"))
+        }
+        if self.max_trace_filter_blocks == 0 {
+            return Err(ConfigError::Zero("max trace filter blocks"))
+        }
+        if self.gas_cap == 0 {
+            return Err(ConfigError::Zero("gas cap"))
+        }
+
+        Ok(())
+    }
+
+    /// Opens the database and spawns the reth components serving `inner`.
+    pub fn build<M: Middleware>(self, inner: M) -> Result<RethMiddleware<M>, InitError> {
+        self.validate()?;
+
+        let (reth_api, reth_filter, reth_trace, reth_debug, head_update_sender, shutdown_handle) =
+            RethMiddleware::<M>::try_new(&self)?;
+
+        Ok(RethMiddleware {
+            inner,
+            reth_api,
+            reth_filter,
+            reth_trace,
+            reth_debug,
+            head_update_sender,
+            shutdown_handle,
+            max_trace_filter_blocks: self.max_trace_filter_blocks,
+            max_logs_per_response: self.max_logs_per_response,
+            ens_cache: Default::default(),
+            fallback: Arc::new(Fallback {
+                policy: self.fallback_policy,
+                observer: self.served_by_observer,
+                stats: Default::default(),
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> RethMiddlewareBuilder {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        RethMiddlewareBuilder::new("/tmp/reth", runtime.handle().clone(), 1)
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(builder().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_zero_limits() {
+        assert_eq!(
+            builder().max_tracing_requests(0).validate(),
+            Err(ConfigError::Zero("max tracing requests"))
+        );
+        assert_eq!(
+            builder().max_logs_per_response(0).validate(),
+            Err(ConfigError::Zero("max logs per response"))
+        );
+        assert_eq!(
+            builder().max_trace_filter_blocks(0).validate(),
+            Err(ConfigError::Zero("max trace filter blocks"))
+        );
+        assert_eq!(builder().gas_cap(0).validate(), Err(ConfigError::Zero("gas cap")));
+    }
+
+    #[test]
+    fn rejects_invalid_percentile() {
+        let config = GasPriceOracleConfig { percentile: 101, ..Default::default() };
+        assert_eq!(
+            builder().gas_oracle_config(config).validate(),
+            Err(ConfigError::InvalidPercentile(101))
+        );
+    }
+}
@@ -0,0 +1,525 @@
+//! Simulation of transaction bundles, in the style of `eth_callBundle`.
+//!
+//! The transactions of a bundle are executed one after another on top of the state of a block,
+//! each seeing the changes of the previous ones. Nothing is written to the database.
+
+use crate::{
+    type_conversions::{ToEthers, ToReth},
+    RethMiddleware, RethMiddlewareError,
+};
+use std::fmt;
+
+use ethers::{
+    abi::{self, ParamType, Token},
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address as EthersAddress, BlockId as EthersBlockId,
+        Bytes as EthersBytes, Log as EthersLog, H256 as EthersH256, U256 as EthersU256,
+        U64 as EthersU64,
+    },
+};
+use reth_primitives::{Address, Header, TransactionSigned, U256};
+use reth_provider::BlockReaderIdExt;
+use reth_revm::{
+    database::{State, SubState},
+    db::{CacheDB, DatabaseRef},
+    env::tx_env_with_recovered,
+    primitives::{
+        BlockEnv, Env, ExecutionResult, Halt, Log, ResultAndState, State as EvmState, TransactTo,
+        TxEnv,
+    },
+    Database, DatabaseCommit, EVM,
+};
+use reth_rpc::eth::{
+    error::{EthApiError, EthResult},
+    EthTransactions,
+};
+
+/// The selector of `Error(string)`, the revert reason of `require` and `revert`.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// The seconds between a block and the next one simulated on top of it.
+pub(crate) const BLOCK_TIME: u64 = 12;
+
+/// A transaction of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleTransaction {
+    /// A signed transaction, encoded as for `eth_sendRawTransaction`.
+    Signed(EthersBytes),
+    /// A transaction sent from its `from` address without a signature. Missing fees default to
+    /// the base fee and a missing nonce is not checked.
+    Unsigned(TypedTransaction),
+}
+
+impl From<EthersBytes> for BundleTransaction {
+    fn from(raw: EthersBytes) -> Self {
+        BundleTransaction::Signed(raw)
+    }
+}
+
+impl From<TypedTransaction> for BundleTransaction {
+    fn from(tx: TypedTransaction) -> Self {
+        BundleTransaction::Unsigned(tx)
+    }
+}
+
+impl BundleTransaction {
+    /// Returns the hash of a signed transaction and the environment to execute it in.
+    pub(crate) fn into_tx_env(
+        self,
+        block_env: &BlockEnv,
+    ) -> EthResult<(Option<EthersH256>, TxEnv)> {
+        match self {
+            BundleTransaction::Signed(raw) => {
+                let tx = TransactionSigned::decode_enveloped(raw.0.into())
+                    .map_err(|_| EthApiError::FailedToDecodeSignedTransaction)?;
+                let tx = tx.into_ecrecovered().ok_or(EthApiError::InvalidTransactionSignature)?;
+                Ok((Some(tx.hash().into_ethers()), tx_env_with_recovered(&tx)))
+            }
+            BundleTransaction::Unsigned(tx) => Ok((None, typed_tx_env(&tx, block_env))),
+        }
+    }
+}
+
+/// Builds the environment of an unsigned transaction.
+pub(crate) fn typed_tx_env(tx: &TypedTransaction, block_env: &BlockEnv) -> TxEnv {
+    let gas_priority_fee =
+        tx.as_eip1559_ref().and_then(|tx| tx.max_priority_fee_per_gas).into_reth();
+    let access_list = tx
+        .access_list()
+        .map(|access_list| {
+            access_list
+                .0
+                .iter()
+                .map(|item| {
+                    let slots = item.storage_keys.iter().map(|key| U256::from_be_bytes(key.0));
+                    (item.address.into_reth(), slots.collect())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    TxEnv {
+        caller: tx.from().copied().unwrap_or_default().into_reth(),
+        gas_limit: tx
+            .gas()
+            .map(|gas| gas.as_u64())
+            .unwrap_or_else(|| u64::try_from(block_env.gas_limit).unwrap_or(u64::MAX)),
+        gas_price: tx.gas_price().map(ToReth::into_reth).unwrap_or(block_env.basefee),
+        gas_priority_fee,
+        transact_to: match tx.to_addr() {
+            Some(to) => TransactTo::Call(to.into_reth()),
+            None => TransactTo::create(),
+        },
+        value: tx.value().copied().unwrap_or_default().into_reth(),
+        data: tx.data().cloned().unwrap_or_default().0,
+        chain_id: tx.chain_id().map(|chain_id| chain_id.as_u64()),
+        nonce: tx.nonce().map(|nonce| nonce.as_u64()),
+        access_list,
+        ..Default::default()
+    }
+}
+
+/// Why an execution failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ExecutionFailure {
+    /// The execution reverted, with the decoded revert reason.
+    Revert(String),
+    /// The execution halted, e.g. because it ran out of gas.
+    Halt(Halt),
+    /// The transaction failed validation, e.g. because of its nonce or the balance of its
+    /// sender, and was not executed.
+    Invalid(String),
+}
+
+impl fmt::Display for ExecutionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionFailure::Revert(reason) => write!(f, "execution reverted: {reason}"),
+            ExecutionFailure::Halt(reason) => write!(f, "execution halted: {reason:?}"),
+            ExecutionFailure::Invalid(err) => write!(f, "invalid transaction: {err}"),
+        }
+    }
+}
+
+/// The outcome of an execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExecutionOutcome {
+    pub(crate) gas_used: u64,
+    /// The return data, or the revert data if the execution reverted. Empty if it halted.
+    pub(crate) output: EthersBytes,
+    /// The logs of a successful execution.
+    pub(crate) logs: Vec<Log>,
+    pub(crate) failure: Option<ExecutionFailure>,
+}
+
+impl ExecutionOutcome {
+    fn invalid(err: impl fmt::Display) -> Self {
+        Self {
+            gas_used: 0,
+            output: EthersBytes::default(),
+            logs: Vec::new(),
+            failure: Some(ExecutionFailure::Invalid(err.to_string())),
+        }
+    }
+}
+
+impl From<ExecutionResult> for ExecutionOutcome {
+    fn from(result: ExecutionResult) -> Self {
+        let gas_used = result.gas_used();
+        let (output, logs, failure) = match result {
+            ExecutionResult::Success { output, logs, .. } => {
+                (output.into_data().into(), logs, None)
+            }
+            ExecutionResult::Revert { output, .. } => {
+                let reason = revert_reason(&output);
+                (output.into(), Vec::new(), Some(ExecutionFailure::Revert(reason)))
+            }
+            ExecutionResult::Halt { reason, .. } => {
+                (EthersBytes::default(), Vec::new(), Some(ExecutionFailure::Halt(reason)))
+            }
+        };
+        Self { gas_used, output, logs, failure }
+    }
+}
+
+/// A transaction executed on top of the transactions before it.
+#[derive(Debug, Clone)]
+pub(crate) struct ExecutedTransaction {
+    pub(crate) caller: Address,
+    pub(crate) transact_to: TransactTo,
+    pub(crate) outcome: ExecutionOutcome,
+    pub(crate) gas_price: U256,
+    pub(crate) coinbase_payment: U256,
+}
+
+/// Executes a transaction and commits its changes to `db`.
+///
+/// A transaction that fails validation changes nothing and is reported as
+/// [`ExecutionFailure::Invalid`], only database errors are returned.
+pub(crate) fn transact_and_commit<DB>(
+    db: &mut CacheDB<DB>,
+    env: Env,
+) -> EthResult<ExecutedTransaction>
+where
+    DB: DatabaseRef,
+    EthApiError: From<DB::Error>,
+{
+    let (executed, state) = transact(db, env)?;
+    db.commit(state);
+    Ok(executed)
+}
+
+/// Executes a transaction like [`transact_and_commit`], returning its changes instead of
+/// committing them.
+pub(crate) fn transact<DB>(
+    db: &mut CacheDB<DB>,
+    env: Env,
+) -> EthResult<(ExecutedTransaction, EvmState)>
+where
+    DB: DatabaseRef,
+    EthApiError: From<DB::Error>,
+{
+    let coinbase = env.block.coinbase;
+    let coinbase_before = db.basic(coinbase)?.map(|account| account.balance).unwrap_or_default();
+    let caller = env.tx.caller;
+    let transact_to = env.tx.transact_to.clone();
+    let gas_price = env.effective_gas_price();
+
+    let mut evm = EVM::with_env(env);
+    evm.database(&mut *db);
+    let (outcome, state) = match evm.transact().map_err(EthApiError::from) {
+        Ok(ResultAndState { result, state }) => (result.into(), state),
+        Err(EthApiError::InvalidTransaction(err)) => {
+            (ExecutionOutcome::invalid(err), EvmState::default())
+        }
+        Err(err) => return Err(err),
+    };
+    let coinbase_after =
+        state.get(&coinbase).map(|account| account.info.balance).unwrap_or(coinbase_before);
+
+    let executed = ExecutedTransaction {
+        caller,
+        transact_to,
+        outcome,
+        gas_price,
+        coinbase_payment: coinbase_after.saturating_sub(coinbase_before),
+    };
+    Ok((executed, state))
+}
+
+/// Returns the environment of the block after `parent`, [`BLOCK_TIME`] seconds later, with the
+/// base fee that follows from the gas used by `parent`. The other fields are inherited.
+pub(crate) fn next_block_env(parent: &Header, mut block_env: BlockEnv) -> BlockEnv {
+    block_env.number = U256::from(parent.number + 1);
+    block_env.timestamp = U256::from(parent.timestamp + BLOCK_TIME);
+    if let Some(base_fee) = parent.next_block_base_fee() {
+        block_env.basefee = U256::from(base_fee);
+    }
+    block_env
+}
+
+/// The outcome of a transaction of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleTransactionResult {
+    /// The hash of a signed transaction.
+    pub tx_hash: Option<EthersH256>,
+    pub from: EthersAddress,
+    /// The recipient, `None` for contract creations.
+    pub to: Option<EthersAddress>,
+    pub gas_used: u64,
+    /// The effective gas price paid per unit of gas.
+    pub gas_price: EthersU256,
+    /// The increase of the coinbase balance, from fees and direct transfers.
+    pub coinbase_payment: EthersU256,
+    pub logs: Vec<EthersLog>,
+    /// The return data, or the revert data if the transaction reverted.
+    pub output: EthersBytes,
+    /// The decoded reason if the transaction reverted, or the revert data in hex if it cannot
+    /// be decoded.
+    pub revert: Option<String>,
+    /// Why the execution halted, e.g. because it ran out of gas, or why the transaction is
+    /// invalid.
+    pub error: Option<String>,
+}
+
+/// The outcome of a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallBundleResponse {
+    /// The block whose state the bundle was executed on.
+    pub state_block_number: u64,
+    /// The total payment to the coinbase.
+    pub coinbase_diff: EthersU256,
+    pub gas_used: u64,
+    /// The coinbase payment per unit of gas, the effective gas price of the whole bundle.
+    pub bundle_gas_price: EthersU256,
+    pub results: Vec<BundleTransactionResult>,
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Executes `transactions` in order on top of the state of `block`, in the environment of
+    /// the block after it.
+    ///
+    /// The environment is numbered one after `block`, 12 seconds later, with the base fee that
+    /// follows from the gas used by `block`. Reverted, halted and invalid transactions do not
+    /// stop the bundle, their outcome is reported in the results. A transaction that cannot be
+    /// decoded or whose signature cannot be recovered fails the whole bundle.
+    pub async fn call_bundle<T, B>(
+        &self,
+        transactions: Vec<T>,
+        block: B,
+    ) -> Result<CallBundleResponse, RethMiddlewareError<M>>
+    where
+        T: Into<BundleTransaction>,
+        B: Into<EthersBlockId>,
+    {
+        let block: EthersBlockId = block.into();
+        let (cfg, block_env, at) = self.reth_api.evm_env_at(block.into_reth()).await?;
+        let parent = self
+            .reth_client()
+            .header_by_id(at)
+            .map_err(EthApiError::from)?
+            .ok_or(RethMiddlewareError::BlockNotFound)?;
+        let state_block_number = parent.number;
+        let block_env = next_block_env(&parent, block_env);
+
+        let transactions = transactions
+            .into_iter()
+            .map(|tx| tx.into().into_tx_env(&block_env))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (tx_hashes, tx_envs): (Vec<_>, Vec<_>) = transactions.into_iter().unzip();
+
+        let executed = self
+            .reth_api
+            .spawn_with_state_at_block(at, move |state| {
+                let mut db = SubState::new(State::new(state));
+                let mut executed = Vec::with_capacity(tx_envs.len());
+                for tx in tx_envs {
+                    let env = Env { cfg: cfg.clone(), block: block_env.clone(), tx };
+                    executed.push(transact_and_commit(&mut db, env)?);
+                }
+                Ok(executed)
+            })
+            .await?;
+
+        let mut log_index = 0;
+        let results: Vec<BundleTransactionResult> = tx_hashes
+            .into_iter()
+            .zip(executed)
+            .enumerate()
+            .map(|(index, (tx_hash, executed))| {
+                bundle_result(index, tx_hash, executed, &mut log_index)
+            })
+            .collect();
+
+        let coinbase_diff = results
+            .iter()
+            .fold(EthersU256::zero(), |total, result| total + result.coinbase_payment);
+        let gas_used = results.iter().map(|result| result.gas_used).sum();
+        let bundle_gas_price =
+            if gas_used == 0 { EthersU256::zero() } else { coinbase_diff / gas_used };
+
+        Ok(CallBundleResponse {
+            state_block_number,
+            coinbase_diff,
+            gas_used,
+            bundle_gas_price,
+            results,
+        })
+    }
+}
+
+/// Reports the outcome of the transaction at `index`, numbering its logs from `log_index`.
+pub(crate) fn bundle_result(
+    index: usize,
+    tx_hash: Option<EthersH256>,
+    executed: ExecutedTransaction,
+    log_index: &mut u64,
+) -> BundleTransactionResult {
+    let ExecutionOutcome { gas_used, output, logs, failure } = executed.outcome;
+    let (revert, error) = match failure {
+        Some(ExecutionFailure::Revert(reason)) => (Some(reason), None),
+        Some(failure) => (None, Some(failure.to_string())),
+        None => (None, None),
+    };
+
+    let logs = logs
+        .into_iter()
+        .map(|log| {
+            let log = ethers_log(log, tx_hash, index, *log_index);
+            *log_index += 1;
+            log
+        })
+        .collect();
+
+    BundleTransactionResult {
+        tx_hash,
+        from: executed.caller.into_ethers(),
+        to: match executed.transact_to {
+            TransactTo::Call(to) => Some(to.into_ethers()),
+            TransactTo::Create(_) => None,
+        },
+        gas_used,
+        gas_price: executed.gas_price.into_ethers(),
+        coinbase_payment: executed.coinbase_payment.into_ethers(),
+        logs,
+        output,
+        revert,
+        error,
+    }
+}
+
+/// Converts a log emitted by a simulated transaction, which is not part of any block.
+pub(crate) fn ethers_log(
+    log: Log,
+    tx_hash: Option<EthersH256>,
+    tx_index: usize,
+    log_index: u64,
+) -> EthersLog {
+    EthersLog {
+        address: log.address.into_ethers(),
+        topics: log.topics.into_ethers(),
+        data: log.data.into(),
+        transaction_hash: tx_hash,
+        transaction_index: Some(EthersU64::from(tx_index)),
+        log_index: Some(log_index.into()),
+        removed: Some(false),
+        ..Default::default()
+    }
+}
+
+/// Decodes the reason of a `require` or `revert`, or returns the revert data in hex.
+pub(crate) fn revert_reason(output: &[u8]) -> String {
+    output
+        .strip_prefix(&ERROR_SELECTOR)
+        .and_then(|data| abi::decode(&[ParamType::String], data).ok())
+        .and_then(|mut tokens| tokens.pop())
+        .and_then(Token::into_string)
+        .unwrap_or_else(|| EthersBytes::from(output.to_vec()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest};
+
+    #[test]
+    fn decodes_revert_reasons() {
+        let mut output = ERROR_SELECTOR.to_vec();
+        output.extend(abi::encode(&[Token::String("insufficient balance".to_string())]));
+        assert_eq!(revert_reason(&output), "insufficient balance");
+
+        assert_eq!(revert_reason(&[0xde, 0xad]), "0xdead");
+        assert_eq!(revert_reason(&[]), "0x");
+    }
+
+    #[test]
+    fn execution_outcomes() {
+        let revert: ExecutionOutcome =
+            ExecutionResult::Revert { gas_used: 21_000, output: vec![0xde].into() }.into();
+        assert_eq!(revert.output, EthersBytes::from(vec![0xde]));
+        assert_eq!(revert.failure.unwrap().to_string(), "execution reverted: 0xde");
+
+        let invalid = ExecutionOutcome::invalid("nonce too low");
+        assert_eq!(invalid.gas_used, 0);
+        assert_eq!(invalid.failure.unwrap().to_string(), "invalid transaction: nonce too low");
+    }
+
+    #[test]
+    fn next_block_env_follows_parent() {
+        let parent = Header {
+            number: 10,
+            timestamp: 1_000,
+            gas_limit: 30_000_000,
+            gas_used: 30_000_000,
+            base_fee_per_gas: Some(1_000_000_000),
+            ..Default::default()
+        };
+        let block_env = BlockEnv { coinbase: Address::repeat_byte(1), ..Default::default() };
+
+        let next = next_block_env(&parent, block_env);
+        assert_eq!(next.number, U256::from(11));
+        assert_eq!(next.timestamp, U256::from(1_012));
+        // a full block raises the base fee by an eighth
+        assert_eq!(next.basefee, U256::from(1_125_000_000));
+        assert_eq!(next.coinbase, Address::repeat_byte(1));
+    }
+
+    #[test]
+    fn unsigned_tx_env() {
+        let block_env = BlockEnv {
+            gas_limit: U256::from(30_000_000),
+            basefee: U256::from(7),
+            ..Default::default()
+        };
+        let from = EthersAddress::repeat_byte(1);
+        let to = EthersAddress::repeat_byte(2);
+        let (reth_from, reth_to): (Address, Address) = (from.into_reth(), to.into_reth());
+
+        let legacy: TypedTransaction = TransactionRequest::new().from(from).to(to).value(5).into();
+        let env = typed_tx_env(&legacy, &block_env);
+        assert_eq!(env.caller, reth_from);
+        assert_eq!(env.transact_to, TransactTo::Call(reth_to));
+        assert_eq!(env.gas_limit, 30_000_000);
+        assert_eq!(env.gas_price, U256::from(7));
+        assert_eq!(env.value, U256::from(5));
+        assert_eq!(env.nonce, None);
+
+        let eip1559: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(from)
+            .gas(50_000)
+            .max_fee_per_gas(100)
+            .max_priority_fee_per_gas(2)
+            .nonce(3)
+            .into();
+        let env = typed_tx_env(&eip1559, &block_env);
+        assert_eq!(env.transact_to, TransactTo::create());
+        assert_eq!(env.gas_limit, 50_000);
+        assert_eq!(env.gas_price, U256::from(100));
+        assert_eq!(env.gas_priority_fee, Some(U256::from(2)));
+        assert_eq!(env.nonce, Some(3));
+    }
+}
@@ -0,0 +1,235 @@
+//! Sequences of calls sharing state, in the style of `eth_callMany`.
+//!
+//! Calls are grouped in [`CallManyBundle`]s, each with its own block overrides. All calls run
+//! one after another on the state of a [`StateContext`], every call seeing the changes of the
+//! calls before it. Nothing is written to the database.
+
+use crate::{
+    bundle::{transact_and_commit, typed_tx_env, ExecutionFailure, ExecutionOutcome},
+    overrides::state_overrides,
+    type_conversions::ToReth,
+    RethMiddleware, RethMiddlewareError,
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        spoof, transaction::eip2718::TypedTransaction, BlockId as EthersBlockId, BlockOverrides,
+        Bytes as EthersBytes,
+    },
+};
+use reth_primitives::{BlockId, U256};
+use reth_provider::BlockReaderIdExt;
+use reth_revm::{
+    database::{State, SubState},
+    env::tx_env_with_recovered,
+    primitives::{BlockEnv, Env, TxEnv},
+};
+use reth_rpc::eth::{
+    error::EthApiError,
+    revm_utils::{apply_block_overrides, apply_state_overrides},
+    EthTransactions,
+};
+
+/// Calls executed in the same block environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallManyBundle {
+    pub transactions: Vec<TypedTransaction>,
+    /// Replaces fields of the block environment for the calls of this bundle.
+    pub block_overrides: Option<BlockOverrides>,
+}
+
+impl CallManyBundle {
+    pub fn new(transactions: Vec<TypedTransaction>) -> Self {
+        Self { transactions, block_overrides: None }
+    }
+
+    /// Sets the block overrides.
+    pub fn block_overrides(mut self, block_overrides: BlockOverrides) -> Self {
+        self.block_overrides = Some(block_overrides);
+        self
+    }
+}
+
+/// The state the calls are executed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateContext {
+    pub block: EthersBlockId,
+    /// Executes the calls after the transactions of `block` before this index instead of after
+    /// the whole block. At most the number of transactions of `block`.
+    pub transaction_index: Option<usize>,
+}
+
+impl StateContext {
+    /// The state after all transactions of `block`.
+    pub fn new(block: impl Into<EthersBlockId>) -> Self {
+        Self { block: block.into(), transaction_index: None }
+    }
+
+    /// The state before the transaction at `index` of `block`.
+    pub fn at_transaction(block: impl Into<EthersBlockId>, index: usize) -> Self {
+        Self { block: block.into(), transaction_index: Some(index) }
+    }
+}
+
+/// The outcome of a call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallResponse {
+    /// The return data, or the revert data if the call reverted.
+    pub value: Option<EthersBytes>,
+    /// Why the call failed, with the decoded revert reason if it reverted.
+    pub error: Option<String>,
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Executes the calls of `bundles` in order on the state of `state_context`, with the
+    /// `overrides` applied before the first call.
+    ///
+    /// Calls are executed in the environment of the context block, without a signature.
+    /// Reverted, halted and invalid calls do not stop the sequence, their outcome is reported in
+    /// the responses, one list per bundle. A transaction index past the end of the block is
+    /// rejected with [`RethMiddlewareError::InvalidParams`].
+    pub async fn call_many(
+        &self,
+        bundles: Vec<CallManyBundle>,
+        state_context: StateContext,
+        overrides: Option<spoof::State>,
+    ) -> Result<Vec<Vec<CallResponse>>, RethMiddlewareError<M>> {
+        let (mut cfg, block_env, at) =
+            self.reth_api.evm_env_at(state_context.block.into_reth()).await?;
+        cfg.disable_base_fee = true;
+        cfg.disable_eip3607 = true;
+
+        // the transactions of the block before the index are replayed on the parent state
+        let (state_at, replay) = match state_context.transaction_index {
+            Some(index) => {
+                let block = self
+                    .reth_client()
+                    .block_by_id(at)
+                    .map_err(EthApiError::from)?
+                    .ok_or(RethMiddlewareError::BlockNotFound)?;
+                if index > block.body.len() {
+                    return Err(RethMiddlewareError::InvalidParams(format!(
+                        "transaction index {index} is past the end of the block, which has {} \
+                         transactions",
+                        block.body.len()
+                    )))
+                }
+                let replay = block
+                    .body
+                    .into_iter()
+                    .take(index)
+                    .map(|tx| {
+                        tx.into_ecrecovered().map(|tx| tx_env_with_recovered(&tx)).ok_or_else(
+                            || RethMiddlewareError::Conversion("invalid signature in block".into()),
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                (BlockId::from(block.parent_hash), replay)
+            }
+            None => (at, Vec::new()),
+        };
+
+        let bundles: Vec<(BlockEnv, Vec<TxEnv>)> = bundles
+            .into_iter()
+            .map(|bundle| {
+                let mut block_env = block_env.clone();
+                if let Some(block_overrides) = bundle.block_overrides {
+                    apply_block_overrides(block_overrides.into_reth(), &mut block_env);
+                }
+                let txs =
+                    bundle.transactions.iter().map(|tx| call_tx_env(tx, &block_env)).collect();
+                (block_env, txs)
+            })
+            .collect();
+        let overrides = overrides.map(state_overrides::<M>).transpose()?;
+
+        let responses = self
+            .reth_api
+            .spawn_with_state_at_block(state_at, move |state| {
+                let mut db = SubState::new(State::new(state));
+                for tx in replay {
+                    let env = Env { cfg: cfg.clone(), block: block_env.clone(), tx };
+                    transact_and_commit(&mut db, env)?;
+                }
+                if let Some(overrides) = overrides {
+                    apply_state_overrides(overrides, &mut db)?;
+                }
+
+                let mut responses = Vec::with_capacity(bundles.len());
+                for (block_env, txs) in bundles {
+                    let mut bundle_responses = Vec::with_capacity(txs.len());
+                    for tx in txs {
+                        let env = Env { cfg: cfg.clone(), block: block_env.clone(), tx };
+                        let executed = transact_and_commit(&mut db, env)?;
+                        bundle_responses.push(call_response(executed.outcome));
+                    }
+                    responses.push(bundle_responses);
+                }
+                Ok(responses)
+            })
+            .await?;
+
+        Ok(responses)
+    }
+}
+
+/// Builds the environment of a call, which pays no fees unless it sets a gas price.
+pub(crate) fn call_tx_env(tx: &TypedTransaction, block_env: &BlockEnv) -> TxEnv {
+    let mut env = typed_tx_env(tx, block_env);
+    if tx.gas_price().is_none() {
+        env.gas_price = U256::ZERO;
+    }
+    env
+}
+
+pub(crate) fn call_response(outcome: ExecutionOutcome) -> CallResponse {
+    let ExecutionOutcome { output, failure, .. } = outcome;
+    let value = match failure {
+        None | Some(ExecutionFailure::Revert(_)) => Some(output),
+        Some(ExecutionFailure::Halt(_) | ExecutionFailure::Invalid(_)) => None,
+    };
+    CallResponse { value, error: failure.map(|failure| failure.to_string()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Eip1559TransactionRequest, TransactionRequest};
+    use reth_revm::primitives::{Eval, ExecutionResult, Output};
+
+    #[test]
+    fn calls_pay_no_fees_by_default() {
+        let block_env = BlockEnv { basefee: U256::from(7), ..Default::default() };
+
+        let call: TypedTransaction = Eip1559TransactionRequest::new().into();
+        assert_eq!(call_tx_env(&call, &block_env).gas_price, U256::ZERO);
+
+        let priced: TypedTransaction = TransactionRequest::new().gas_price(9).into();
+        assert_eq!(call_tx_env(&priced, &block_env).gas_price, U256::from(9));
+    }
+
+    #[test]
+    fn responses() {
+        let success = call_response(
+            ExecutionResult::Success {
+                reason: Eval::Return,
+                gas_used: 21_000,
+                gas_refunded: 0,
+                logs: Vec::new(),
+                output: Output::Call(vec![0x01].into()),
+            }
+            .into(),
+        );
+        assert_eq!(success, CallResponse { value: Some(vec![0x01].into()), error: None });
+
+        let revert = call_response(
+            ExecutionResult::Revert { gas_used: 21_000, output: vec![0xde].into() }.into(),
+        );
+        assert_eq!(revert.value, Some(vec![0xde].into()));
+        assert_eq!(revert.error.as_deref(), Some("execution reverted: 0xde"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Selection of the chain specification the database was synced with.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use thiserror::Error;
+
+use reth_primitives::{AllGenesisFormats, ChainSpec, DEV, GOERLI, H256, MAINNET, SEPOLIA};
+
+/// The chain specification of the database, or how to obtain it.
+#[derive(Debug, Clone)]
+pub enum ChainSource {
+    /// One of the chains known to reth, by chain id.
+    Id(u64),
+    /// A complete chain specification.
+    Spec(Arc<ChainSpec>),
+    /// A geth `genesis.json` or a reth chain specification stored as JSON.
+    GenesisFile(PathBuf),
+    /// The known or registered chain whose genesis hash matches block 0 of the database.
+    Detect,
+}
+
+/// A chain specification that could not be resolved.
+#[derive(Error, Debug)]
+pub enum ChainError {
+    /// The chain id does not belong to a chain known to reth.
+    #[error("unsupported chain id {0}, pass a chain spec or genesis file instead")]
+    UnsupportedChainId(u64),
+
+    /// The genesis file could not be read.
+    #[error("could not read genesis file {path:?}: {source}")]
+    GenesisFile { path: PathBuf, source: std::io::Error },
+
+    /// The genesis file is not a geth genesis or reth chain specification.
+    #[error("invalid genesis file {path:?}: {source}")]
+    InvalidGenesis { path: PathBuf, source: serde_json::Error },
+
+    /// The chain could not be detected because the database has no genesis block.
+    #[error("the database has no genesis block")]
+    MissingGenesis,
+
+    /// No known or registered chain has the genesis hash of the database.
+    #[error("no known chain has genesis hash {0:?}, register its chain spec")]
+    UnknownGenesis(H256),
+
+    /// The chain specification does not belong to the chain of the database.
+    #[error("the chain spec has genesis hash {expected:?} but the database has {found:?}")]
+    GenesisMismatch { expected: H256, found: H256 },
+}
+
+impl ChainSource {
+    /// Reads the chain specification from a geth `genesis.json` or reth chain spec file.
+    pub fn genesis_file<P: AsRef<Path>>(path: P) -> Self {
+        ChainSource::GenesisFile(path.as_ref().to_path_buf())
+    }
+
+    /// Returns the chain specification.
+    ///
+    /// `genesis_hash` is the hash of block 0 in the database, if any. Chain ids and genesis
+    /// hashes are looked up in the `registered` chain specs before the chains known to reth.
+    /// A chain spec whose genesis hash differs from the one of the database is rejected.
+    pub fn resolve(
+        &self,
+        genesis_hash: Option<H256>,
+        registered: &[Arc<ChainSpec>],
+    ) -> Result<Arc<ChainSpec>, ChainError> {
+        let chain_spec = match self {
+            ChainSource::Id(chain_id) => known_chains(registered)
+                .find(|chain_spec| chain_spec.chain.id() == *chain_id)
+                .ok_or(ChainError::UnsupportedChainId(*chain_id))?,
+            ChainSource::Spec(chain_spec) => chain_spec.clone(),
+            ChainSource::GenesisFile(path) => {
+                let file = File::open(path)
+                    .map_err(|source| ChainError::GenesisFile { path: path.clone(), source })?;
+                let genesis: AllGenesisFormats = serde_json::from_reader(BufReader::new(file))
+                    .map_err(|source| ChainError::InvalidGenesis { path: path.clone(), source })?;
+                Arc::new(genesis.into())
+            }
+            ChainSource::Detect => {
+                let genesis_hash = genesis_hash.ok_or(ChainError::MissingGenesis)?;
+                return known_chains(registered)
+                    .find(|chain_spec| chain_spec.genesis_hash() == genesis_hash)
+                    .ok_or(ChainError::UnknownGenesis(genesis_hash))
+            }
+        };
+
+        match genesis_hash {
+            Some(found) if found != chain_spec.genesis_hash() => {
+                Err(ChainError::GenesisMismatch { expected: chain_spec.genesis_hash(), found })
+            }
+            _ => Ok(chain_spec),
+        }
+    }
+}
+
+/// The `registered` chain specs followed by the chains known to reth.
+fn known_chains(registered: &[Arc<ChainSpec>]) -> impl Iterator<Item = Arc<ChainSpec>> + '_ {
+    registered.iter().cloned().chain([
+        MAINNET.clone(),
+        GOERLI.clone(),
+        SEPOLIA.clone(),
+        DEV.clone(),
+    ])
+}
+
+impl From<u64> for ChainSource {
+    fn from(chain_id: u64) -> Self {
+        ChainSource::Id(chain_id)
+    }
+}
+
+impl From<Arc<ChainSpec>> for ChainSource {
+    fn from(chain_spec: Arc<ChainSpec>) -> Self {
+        ChainSource::Spec(chain_spec)
+    }
+}
+
+impl From<ChainSpec> for ChainSource {
+    fn from(chain_spec: ChainSpec) -> Self {
+        ChainSource::Spec(Arc::new(chain_spec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GETH_GENESIS: &str = r#"{
+        "config": {
+            "chainId": 1337133,
+            "homesteadBlock": 0,
+            "eip150Block": 0,
+            "eip155Block": 0,
+            "eip158Block": 0,
+            "byzantiumBlock": 0,
+            "constantinopleBlock": 0,
+            "petersburgBlock": 0,
+            "istanbulBlock": 0,
+            "berlinBlock": 0,
+            "londonBlock": 0,
+            "terminalTotalDifficulty": 0,
+            "terminalTotalDifficultyPassed": true,
+            "shanghaiTime": 0
+        },
+        "nonce": "0x0",
+        "timestamp": "0x0",
+        "extraData": "0x",
+        "gasLimit": "0x1c9c380",
+        "difficulty": "0x0",
+        "mixHash": "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "coinbase": "0x0000000000000000000000000000000000000000",
+        "alloc": {
+            "0x6be02d1d3665660d22ff9624b7be0551ee1ac91b": { "balance": "0x3635c9adc5dea00000" }
+        }
+    }"#;
+
+    fn geth_chain_spec() -> Arc<ChainSpec> {
+        let genesis: AllGenesisFormats = serde_json::from_str(GETH_GENESIS).unwrap();
+        Arc::new(genesis.into())
+    }
+
+    #[test]
+    fn known_chain_ids() {
+        assert_eq!(ChainSource::Id(1).resolve(None, &[]).unwrap().chain, MAINNET.chain);
+        assert_eq!(ChainSource::Id(1337).resolve(None, &[]).unwrap().chain, DEV.chain);
+        assert!(matches!(
+            ChainSource::Id(42).resolve(None, &[]),
+            Err(ChainError::UnsupportedChainId(42))
+        ));
+    }
+
+    #[test]
+    fn registered_chain_id() {
+        let chain_spec = ChainSource::Id(1337133).resolve(None, &[geth_chain_spec()]).unwrap();
+        assert_eq!(chain_spec.chain.id(), 1337133);
+    }
+
+    #[test]
+    fn detect_known_chains() {
+        for known in [MAINNET.clone(), GOERLI.clone(), SEPOLIA.clone(), DEV.clone()] {
+            let chain_spec = ChainSource::Detect.resolve(Some(known.genesis_hash()), &[]).unwrap();
+            assert_eq!(chain_spec.chain, known.chain);
+        }
+    }
+
+    #[test]
+    fn detect_registered_chain() {
+        let registered = geth_chain_spec();
+        let genesis_hash = registered.genesis_hash();
+
+        assert!(matches!(
+            ChainSource::Detect.resolve(Some(genesis_hash), &[]),
+            Err(ChainError::UnknownGenesis(hash)) if hash == genesis_hash
+        ));
+
+        let chain_spec = ChainSource::Detect.resolve(Some(genesis_hash), &[registered]).unwrap();
+        assert_eq!(chain_spec.chain.id(), 1337133);
+    }
+
+    #[test]
+    fn detect_empty_database() {
+        assert!(matches!(ChainSource::Detect.resolve(None, &[]), Err(ChainError::MissingGenesis)));
+    }
+
+    #[test]
+    fn geth_genesis_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genesis.json");
+        std::fs::write(&path, GETH_GENESIS).unwrap();
+
+        let chain_spec = ChainSource::genesis_file(&path).resolve(None, &[]).unwrap();
+
+        assert_eq!(chain_spec.chain.id(), 1337133);
+        assert_eq!(chain_spec.genesis.alloc.len(), 1);
+    }
+
+    #[test]
+    fn genesis_mismatch() {
+        let genesis_hash = DEV.genesis_hash();
+        assert_eq!(
+            ChainSource::Id(1337).resolve(Some(genesis_hash), &[]).unwrap().chain,
+            DEV.chain
+        );
+
+        for source in [ChainSource::Id(1), ChainSource::Spec(MAINNET.clone())] {
+            assert!(matches!(
+                source.resolve(Some(genesis_hash), &[]),
+                Err(ChainError::GenesisMismatch { found, .. }) if found == genesis_hash
+            ));
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genesis.json");
+        std::fs::write(&path, GETH_GENESIS).unwrap();
+        assert!(matches!(
+            ChainSource::genesis_file(&path).resolve(Some(genesis_hash), &[]),
+            Err(ChainError::GenesisMismatch { expected, .. })
+                if expected == geth_chain_spec().genesis_hash()
+        ));
+    }
+
+    #[test]
+    fn missing_genesis_file() {
+        let source = ChainSource::genesis_file("/nonexistent/genesis.json");
+        assert!(matches!(source.resolve(None, &[]), Err(ChainError::GenesisFile { .. })));
+    }
+}
@@ -0,0 +1,152 @@
+//! A revm database over the state of the local reth database.
+//!
+//! [`RethDatabase`] implements revm's [`Database`] and [`DatabaseRef`], so that EVMs outside of
+//! the middleware, e.g. revm executors or foundry forks, can read historical state from the same
+//! database the middleware opens.
+//!
+//! Every lookup reads the database through its own short-lived read transaction, so a
+//! [`RethDatabase`] can be kept for as long as the middleware without holding on to a snapshot of
+//! the database.
+
+use crate::{type_conversions::ToReth, RethClient, RethMiddleware, RethMiddlewareError};
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use ethers::{providers::Middleware, types::BlockId as EthersBlockId};
+use reth_interfaces::Error as RethError;
+use reth_primitives::{Address, BlockId, BlockNumber, H256, U256};
+use reth_provider::{BlockIdReader, StateProviderBox, StateProviderFactory};
+use reth_revm::{
+    database::State,
+    db::DatabaseRef,
+    primitives::{AccountInfo, Bytecode},
+    Database,
+};
+use reth_rpc::eth::error::{EthApiError, EthResult};
+
+/// The state of a block as a revm database.
+///
+/// Accounts and contract code are cached after the first lookup, storage is always read from the
+/// database. No read transaction is kept open between lookups, but the database holds a handle
+/// to the environment like a middleware clone, so it must be dropped before the last middleware
+/// is shut down.
+pub struct RethDatabase {
+    client: RethClient,
+    block_number: BlockNumber,
+    accounts: Mutex<HashMap<Address, Option<AccountInfo>>>,
+    contracts: Mutex<HashMap<H256, Bytecode>>,
+}
+
+// revm executors share databases between threads
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RethDatabase>();
+};
+
+impl fmt::Debug for RethDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RethDatabase")
+            .field("block_number", &self.block_number)
+            .field("cached_accounts", &self.accounts.lock().unwrap().len())
+            .field("cached_contracts", &self.contracts.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RethDatabase {
+    /// Opens the state after the block with the given number.
+    pub fn at_block_number(client: &RethClient, number: BlockNumber) -> EthResult<Self> {
+        // fails early if the state of the block is not available
+        client.history_by_block_number(number)?;
+
+        Ok(Self {
+            client: client.clone(),
+            block_number: number,
+            accounts: Mutex::new(HashMap::new()),
+            contracts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Opens the state after `block`.
+    ///
+    /// Tags and hashes are resolved to a block number once, when the database is opened.
+    pub fn at_block(client: &RethClient, block: EthersBlockId) -> EthResult<Self> {
+        let block: BlockId = block.into_reth();
+        let number = client.block_number_for_id(block)?.ok_or(EthApiError::UnknownBlockNumber)?;
+        Self::at_block_number(client, number)
+    }
+
+    /// Returns the number of the block whose state the database reads.
+    pub fn block_number(&self) -> BlockNumber {
+        self.block_number
+    }
+
+    /// Opens a read transaction on the state of the block for a single lookup.
+    fn state(&self) -> Result<State<StateProviderBox>, RethError> {
+        Ok(State::new(self.client.history_by_block_number(self.block_number)?))
+    }
+}
+
+impl DatabaseRef for RethDatabase {
+    type Error = RethError;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if let Some(account) = self.accounts.lock().unwrap().get(&address) {
+            return Ok(account.clone())
+        }
+
+        let account = DatabaseRef::basic(&self.state()?, address)?;
+        self.accounts.lock().unwrap().insert(address, account.clone());
+        Ok(account)
+    }
+
+    fn code_by_hash(&self, code_hash: H256) -> Result<Bytecode, Self::Error> {
+        if let Some(code) = self.contracts.lock().unwrap().get(&code_hash) {
+            return Ok(code.clone())
+        }
+
+        let code = DatabaseRef::code_by_hash(&self.state()?, code_hash)?;
+        self.contracts.lock().unwrap().insert(code_hash, code.clone());
+        Ok(code)
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        DatabaseRef::storage(&self.state()?, address, index)
+    }
+
+    fn block_hash(&self, number: U256) -> Result<H256, Self::Error> {
+        DatabaseRef::block_hash(&self.state()?, number)
+    }
+}
+
+impl Database for RethDatabase {
+    type Error = RethError;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        DatabaseRef::basic(self, address)
+    }
+
+    fn code_by_hash(&mut self, code_hash: H256) -> Result<Bytecode, Self::Error> {
+        DatabaseRef::code_by_hash(self, code_hash)
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        DatabaseRef::storage(self, address, index)
+    }
+
+    fn block_hash(&mut self, number: U256) -> Result<H256, Self::Error> {
+        DatabaseRef::block_hash(self, number)
+    }
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Opens the state after `block` as a revm database.
+    pub fn database_at<B: Into<EthersBlockId>>(
+        &self,
+        block: B,
+    ) -> Result<RethDatabase, RethMiddlewareError<M>> {
+        Ok(RethDatabase::at_block(self.reth_client(), block.into())?)
+    }
+}
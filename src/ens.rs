@@ -0,0 +1,190 @@
+//! [ENS](https://docs.ens.domains/) resolution against the local state.
+//!
+//! Names are resolved by calling the ENS registry and resolvers at the latest local block, so no
+//! request reaches the inner middleware.
+
+use crate::{type_conversions::ToEthers, RethMiddleware, RethMiddlewareError};
+use std::{collections::HashMap, sync::Mutex};
+
+use ethers::{
+    abi::{self, ParamType, Token},
+    providers::{ens, Middleware},
+    types::{Address as EthersAddress, BlockId as EthersBlockId, Selector, U64 as EthersU64},
+};
+use reth_rpc_api::EthApiServer;
+
+/// How many answers are cached for a block before the cache is cleared.
+pub const ENS_CACHE_SIZE: usize = 1024;
+
+/// An ENS lookup.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum EnsQuery {
+    Address(String),
+    Name(EthersAddress),
+    Text(String, String),
+}
+
+/// Answers of ENS lookups at the latest block.
+///
+/// The cache is cleared whenever a lookup is made at a different block, so that an answer is
+/// never served for a block it was not resolved at.
+#[derive(Debug, Default)]
+pub struct EnsCache {
+    entries: Mutex<EnsCacheEntries>,
+}
+
+#[derive(Debug, Default)]
+struct EnsCacheEntries {
+    block: u64,
+    answers: HashMap<EnsQuery, Token>,
+}
+
+impl EnsCache {
+    fn get(&self, block: u64, query: &EnsQuery) -> Option<Token> {
+        let entries = self.entries.lock().unwrap();
+        if entries.block != block {
+            return None
+        }
+        entries.answers.get(query).cloned()
+    }
+
+    fn insert(&self, block: u64, query: EnsQuery, answer: Token) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.block != block || entries.answers.len() >= ENS_CACHE_SIZE {
+            entries.block = block;
+            entries.answers.clear();
+        }
+        entries.answers.insert(query, answer);
+    }
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Returns the address `ens_name` resolves to.
+    pub(crate) async fn resolve_ens_name(
+        &self,
+        ens_name: &str,
+    ) -> Result<EthersAddress, RethMiddlewareError<M>> {
+        let query = EnsQuery::Address(ens_name.to_string());
+        let answer = self.query_ens(query, ens_name, ens::ADDR_SELECTOR, None).await?;
+
+        answer.into_address().ok_or_else(|| RethMiddlewareError::EnsError(ens_name.to_string()))
+    }
+
+    /// Returns the name `address` reverse resolves to, if that name resolves back to `address`.
+    pub(crate) async fn lookup_ens_address(
+        &self,
+        address: EthersAddress,
+    ) -> Result<String, RethMiddlewareError<M>> {
+        let reverse_name = ens::reverse_address(address);
+        let query = EnsQuery::Name(address);
+        let answer = self.query_ens(query, &reverse_name, ens::NAME_SELECTOR, None).await?;
+        let ens_name = answer
+            .into_string()
+            .ok_or_else(|| RethMiddlewareError::EnsError(reverse_name.clone()))?;
+
+        if self.resolve_ens_name(&ens_name).await? != address {
+            return Err(RethMiddlewareError::EnsNotOwned(ens_name))
+        }
+
+        Ok(ens_name)
+    }
+
+    /// Returns the text record `field` of `ens_name`.
+    pub(crate) async fn resolve_ens_field(
+        &self,
+        ens_name: &str,
+        field: &str,
+    ) -> Result<String, RethMiddlewareError<M>> {
+        let query = EnsQuery::Text(ens_name.to_string(), field.to_string());
+        let parameters = ens::parameterhash(field);
+        let answer =
+            self.query_ens(query, ens_name, ens::FIELD_SELECTOR, Some(&parameters)).await?;
+
+        answer.into_string().ok_or_else(|| RethMiddlewareError::EnsError(ens_name.to_string()))
+    }
+
+    /// Asks the resolver of `ens_name` for the record selected by `selector`, at the latest block.
+    async fn query_ens(
+        &self,
+        query: EnsQuery,
+        ens_name: &str,
+        selector: Selector,
+        parameters: Option<&[u8]>,
+    ) -> Result<Token, RethMiddlewareError<M>> {
+        let latest: EthersU64 = self.reth_api.block_number()?.into_ethers();
+        let block = latest.as_u64();
+        if let Some(answer) = self.ens_cache.get(block, &query) {
+            return Ok(answer)
+        }
+
+        let ens_error = || RethMiddlewareError::EnsError(ens_name.to_string());
+        let block_id = EthersBlockId::from(block);
+
+        let resolver = self
+            .call(&ens::get_resolver(ens::ENS_ADDRESS, ens_name).into(), Some(block_id))
+            .await?;
+        let resolver = decode(ParamType::Address, &resolver)
+            .and_then(Token::into_address)
+            .filter(|resolver| !resolver.is_zero())
+            .ok_or_else(ens_error)?;
+
+        let param = if selector == ens::ADDR_SELECTOR {
+            // reverse resolvers revert on `supportsInterface`, so only address resolvers are
+            // checked
+            let supported = self
+                .call(&ens::supports_interface(resolver, selector).into(), Some(block_id))
+                .await?;
+            if decode(ParamType::Bool, &supported).and_then(Token::into_bool) != Some(true) {
+                return Err(ens_error())
+            }
+            ParamType::Address
+        } else {
+            ParamType::String
+        };
+
+        let data = self
+            .call(&ens::resolve(resolver, selector, ens_name, parameters).into(), Some(block_id))
+            .await?;
+        let answer = decode(param, &data).ok_or_else(ens_error)?;
+
+        self.ens_cache.insert(block, query, answer.clone());
+        Ok(answer)
+    }
+}
+
+/// Decodes a single ABI encoded value, returning `None` for empty or malformed data.
+fn decode(param: ParamType, data: &[u8]) -> Option<Token> {
+    abi::decode(&[param], data).ok()?.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_is_keyed_by_block() {
+        let cache = EnsCache::default();
+        let query = EnsQuery::Address("vitalik.eth".to_string());
+        let answer = Token::Address(EthersAddress::repeat_byte(1));
+
+        cache.insert(1, query.clone(), answer.clone());
+        assert_eq!(cache.get(1, &query), Some(answer.clone()));
+        assert_eq!(cache.get(2, &query), None);
+
+        cache.insert(2, EnsQuery::Name(EthersAddress::repeat_byte(1)), answer);
+        assert_eq!(cache.get(1, &query), None);
+        assert_eq!(cache.get(2, &query), None);
+    }
+
+    #[test]
+    fn decode_rejects_empty_data() {
+        assert_eq!(decode(ParamType::Address, &[]), None);
+        assert_eq!(
+            decode(ParamType::Address, &[0; 32]),
+            Some(Token::Address(EthersAddress::zero()))
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Fallback to the inner middleware for data missing from the local database.
+//!
+//! A partially synced or pruned node can only answer for the blocks and state it has. With a
+//! [`FallbackPolicy`] the middleware retries such requests against the inner middleware, and
+//! records which [`Backend`] served every request.
+
+use crate::{RethMiddleware, RethMiddlewareError};
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use ethers::providers::{Middleware, MiddlewareError};
+
+/// Why the local database could not answer a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingReason {
+    /// The block or transaction is not in the database.
+    NotFound,
+    /// The state of the block was pruned.
+    Pruned,
+}
+
+impl MissingReason {
+    /// Classifies an error of the local reth components.
+    pub fn from_error<M: Middleware>(err: &RethMiddlewareError<M>) -> Option<Self> {
+        match err {
+            RethMiddlewareError::BlockNotFound |
+            RethMiddlewareError::TransactionNotFound |
+            RethMiddlewareError::ResourceNotFound(_) => Some(MissingReason::NotFound),
+            RethMiddlewareError::HistoryPruned(_) => Some(MissingReason::Pruned),
+            _ => None,
+        }
+    }
+}
+
+/// The reasons for which requests are retried against the inner middleware.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FallbackPolicy {
+    /// Retry if the block or transaction is not in the database.
+    pub not_found: bool,
+    /// Retry if the state of the block was pruned.
+    pub pruned: bool,
+}
+
+impl FallbackPolicy {
+    /// Never falls back, the default.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Falls back for every reason.
+    pub fn all() -> Self {
+        Self { not_found: true, pruned: true }
+    }
+
+    /// Returns true if requests missing data for `reason` are retried.
+    pub fn allows(&self, reason: MissingReason) -> bool {
+        match reason {
+            MissingReason::NotFound => self.not_found,
+            MissingReason::Pruned => self.pruned,
+        }
+    }
+}
+
+/// The backend that served a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The local database.
+    Local,
+    /// The inner middleware.
+    Inner,
+}
+
+/// A callback invoked with the name of the [`Middleware`] method and the backend serving it.
+#[derive(Clone)]
+pub struct ServedByObserver(Arc<dyn Fn(&'static str, Backend) + Send + Sync>);
+
+impl ServedByObserver {
+    pub fn new<F>(observer: F) -> Self
+    where
+        F: Fn(&'static str, Backend) + Send + Sync + 'static,
+    {
+        Self(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for ServedByObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ServedByObserver").finish_non_exhaustive()
+    }
+}
+
+/// The number of requests served by each backend.
+#[derive(Debug, Default)]
+pub struct FallbackStats {
+    local: AtomicU64,
+    inner: AtomicU64,
+}
+
+impl FallbackStats {
+    /// Returns how many requests were served by the local database.
+    pub fn served_locally(&self) -> u64 {
+        self.local.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many requests were retried against the inner middleware.
+    pub fn served_by_inner(&self) -> u64 {
+        self.inner.load(Ordering::Relaxed)
+    }
+}
+
+/// The fallback policy of a middleware and the record of the backends that served it.
+#[derive(Debug, Default)]
+pub(crate) struct Fallback {
+    pub(crate) policy: FallbackPolicy,
+    pub(crate) observer: Option<ServedByObserver>,
+    pub(crate) stats: FallbackStats,
+}
+
+impl Fallback {
+    fn record(&self, method: &'static str, backend: Backend) {
+        let counter = match backend {
+            Backend::Local => &self.stats.local,
+            Backend::Inner => &self.stats.inner,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(observer) = &self.observer {
+            (observer.0)(method, backend);
+        }
+    }
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Returns the number of requests served by the local database and the inner middleware.
+    pub fn fallback_stats(&self) -> &FallbackStats {
+        &self.fallback.stats
+    }
+
+    /// Returns the answer of the `local` request to `method`, converted by `into_answer`, unless
+    /// it is missing data and the policy retries the request with `inner`.
+    ///
+    /// `is_missing` tells whether a successful local answer is empty, e.g. `None` for a block.
+    /// `inner` is only awaited if the request is retried.
+    pub(crate) async fn with_fallback<T, R, E, L, F>(
+        &self,
+        method: &'static str,
+        local: L,
+        into_answer: impl FnOnce(R) -> T,
+        is_missing: impl FnOnce(&T) -> bool,
+        inner: F,
+    ) -> Result<T, RethMiddlewareError<M>>
+    where
+        E: Into<RethMiddlewareError<M>>,
+        L: Future<Output = Result<R, E>>,
+        F: Future<Output = Result<T, M::Error>>,
+    {
+        let local = local.await.map(into_answer).map_err(Into::into);
+        let reason = match &local {
+            Ok(answer) => is_missing(answer).then_some(MissingReason::NotFound),
+            Err(err) => MissingReason::from_error(err),
+        };
+
+        match reason {
+            Some(reason) if self.fallback.policy.allows(reason) => {
+                self.fallback.record(method, Backend::Inner);
+                inner.await.map_err(RethMiddlewareError::from_err)
+            }
+            _ => {
+                self.fallback.record(method, Backend::Local);
+                local
+            }
+        }
+    }
+}
+
+/// For answers that are never empty, only errors are retried.
+pub(crate) fn never_missing<T>(_: &T) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy() {
+        assert!(!FallbackPolicy::disabled().allows(MissingReason::NotFound));
+        assert!(FallbackPolicy::all().allows(MissingReason::Pruned));
+
+        let policy = FallbackPolicy { pruned: true, ..Default::default() };
+        assert!(policy.allows(MissingReason::Pruned));
+        assert!(!policy.allows(MissingReason::NotFound));
+    }
+
+    #[test]
+    fn stats_and_observer() {
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observed = served.clone();
+        let fallback = Fallback {
+            observer: Some(ServedByObserver::new(move |method, backend| {
+                observed.lock().unwrap().push((method, backend))
+            })),
+            ..Default::default()
+        };
+
+        fallback.record("get_block", Backend::Local);
+        fallback.record("get_block", Backend::Inner);
+        fallback.record("get_transaction", Backend::Inner);
+
+        assert_eq!(fallback.stats.served_locally(), 1);
+        assert_eq!(fallback.stats.served_by_inner(), 2);
+        assert_eq!(
+            *served.lock().unwrap(),
+            vec![
+                ("get_block", Backend::Local),
+                ("get_block", Backend::Inner),
+                ("get_transaction", Backend::Inner)
+            ]
+        );
+    }
+}
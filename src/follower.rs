@@ -0,0 +1,269 @@
+//! Follows the canonical head of a database that is written to by a separate reth node.
+//!
+//! The middleware opens the database read-only, so the blockchain tree never learns about blocks
+//! the node commits. The [`HeadFollower`] polls the `CanonicalHeaders` table instead, reports every
+//! head change as a [`HeadUpdate`] and keeps the provider's view of the chain head current.
+
+use crate::{init::view, RethClient};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use reth_db::{
+    cursor::DbCursorRO,
+    mdbx::{Env, WriteMap},
+    tables,
+    transaction::DbTx,
+    DatabaseError,
+};
+use reth_primitives::{BlockNumHash, BlockNumber, H256};
+use reth_provider::{CanonChainTracker, HeaderProvider};
+use reth_rpc::eth::cache::EthStateCache;
+use tokio::{
+    sync::broadcast,
+    time::{interval, MissedTickBehavior},
+};
+
+/// How often the follower checks the database for a new head by default.
+pub const DEFAULT_HEAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many recent canonical hashes the follower remembers to locate the fork point of a reorg.
+pub const REORG_WINDOW: u64 = 64;
+
+/// How many head updates are buffered for a subscriber that falls behind.
+pub const HEAD_UPDATE_CHANNEL_CAPACITY: usize = 128;
+
+/// A change of the canonical head observed in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadUpdate {
+    /// The head before the update.
+    pub old_tip: BlockNumHash,
+    /// The head after the update.
+    pub new_tip: BlockNumHash,
+    /// Blocks that are no longer canonical, newest first. Empty unless the update is a reorg.
+    pub reverted: Vec<BlockNumHash>,
+    /// Blocks that became canonical, oldest first.
+    pub committed: Vec<BlockNumHash>,
+}
+
+impl HeadUpdate {
+    /// Returns true if previously canonical blocks were replaced.
+    pub fn is_reorg(&self) -> bool {
+        !self.reverted.is_empty()
+    }
+}
+
+/// Polls the database for head changes and broadcasts them as [`HeadUpdate`]s.
+pub struct HeadFollower {
+    db: Arc<Env<WriteMap>>,
+    provider: RethClient,
+    state_cache: EthStateCache,
+    sender: broadcast::Sender<HeadUpdate>,
+    poll_interval: Duration,
+    /// Recently seen canonical hashes, covering at most [`REORG_WINDOW`] blocks below the tip.
+    recent: BTreeMap<BlockNumber, H256>,
+}
+
+impl HeadFollower {
+    pub fn new(
+        db: Arc<Env<WriteMap>>,
+        provider: RethClient,
+        state_cache: EthStateCache,
+        sender: broadcast::Sender<HeadUpdate>,
+        poll_interval: Duration,
+    ) -> Self {
+        Self { db, provider, state_cache, sender, poll_interval, recent: BTreeMap::new() }
+    }
+
+    /// Polls the database until the task is dropped.
+    pub async fn run(mut self) {
+        let mut interval = interval(self.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            // a failed read, e.g. while the node is resizing the environment, is retried on the
+            // next tick
+            match self.poll() {
+                Ok(Some(update)) => {
+                    self.on_update(&update).await;
+                    // there may be no subscribers yet
+                    let _ = self.sender.send(update);
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::debug!(
+                        target: "ethers_reth::follower",
+                        %err,
+                        "failed to read the database head"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reads the current head and compares it against the recently seen canonical hashes.
+    ///
+    /// The first successful poll only records the head and never yields an update.
+    fn poll(&mut self) -> Result<Option<HeadUpdate>, DatabaseError> {
+        let recent = &self.recent;
+        let (update, window) = view(&self.db, |tx| -> Result<_, DatabaseError> {
+            let Some((number, hash)) = tx.cursor_read::<tables::CanonicalHeaders>()?.last()? else {
+                return Ok((None, BTreeMap::new()))
+            };
+            let new_tip = BlockNumHash::new(number, hash);
+
+            let canonical_hash = |number| tx.get::<tables::CanonicalHeaders>(number);
+            let update = match recent.last_key_value() {
+                Some((&old_number, &old_hash)) if old_number == number && old_hash == hash => None,
+                Some((&old_number, &old_hash)) => Some(diff_heads(
+                    recent,
+                    BlockNumHash::new(old_number, old_hash),
+                    new_tip,
+                    &canonical_hash,
+                )?),
+                None => None,
+            };
+
+            let mut window = BTreeMap::new();
+            for number in number.saturating_sub(REORG_WINDOW)..=number {
+                if let Some(hash) = canonical_hash(number)? {
+                    window.insert(number, hash);
+                }
+            }
+
+            Ok((update, window))
+        })??;
+
+        if !window.is_empty() {
+            self.recent = window;
+        }
+
+        Ok(update)
+    }
+
+    /// Points the provider at the new head and warms the state cache with the last
+    /// [`REORG_WINDOW`] new blocks, so that catching up after a long pause does not read every
+    /// missed block.
+    ///
+    /// The state cache is keyed by block hash and cannot remove entries, so blocks reverted by a
+    /// reorg stay cached until they are evicted by newer blocks. They can no longer be reached
+    /// through a block number or tag once the provider follows the new head.
+    async fn on_update(&self, update: &HeadUpdate) {
+        if let Ok(Some(header)) = self.provider.sealed_header(update.new_tip.number) {
+            self.provider.set_canonical_head(header);
+        }
+
+        for block in blocks_to_warm(&update.committed) {
+            let _ = self.state_cache.get_block_and_receipts(block.hash).await;
+        }
+    }
+}
+
+/// Computes the update between the last seen head and `new_tip`.
+///
+/// The fork point is the highest remembered block whose hash still matches the database. If the
+/// reorg is deeper than the remembered window, every remembered block is reported as reverted.
+fn diff_heads<F>(
+    recent: &BTreeMap<BlockNumber, H256>,
+    old_tip: BlockNumHash,
+    new_tip: BlockNumHash,
+    canonical_hash: F,
+) -> Result<HeadUpdate, DatabaseError>
+where
+    F: Fn(BlockNumber) -> Result<Option<H256>, DatabaseError>,
+{
+    let mut reverted = Vec::new();
+    let mut fork_number = None;
+    for (&number, &hash) in recent.iter().rev() {
+        if canonical_hash(number)? == Some(hash) {
+            fork_number = Some(number);
+            break
+        }
+        reverted.push(BlockNumHash::new(number, hash));
+    }
+
+    let first_committed = match fork_number {
+        Some(number) => number + 1,
+        None => recent.keys().next().copied().unwrap_or(new_tip.number),
+    };
+
+    let mut committed = Vec::new();
+    for number in first_committed..=new_tip.number {
+        if let Some(hash) = canonical_hash(number)? {
+            committed.push(BlockNumHash::new(number, hash));
+        }
+    }
+
+    Ok(HeadUpdate { old_tip, new_tip, reverted, committed })
+}
+
+/// Returns the newest of `committed` that fit in the reorg window.
+fn blocks_to_warm(committed: &[BlockNumHash]) -> &[BlockNumHash] {
+    &committed[committed.len().saturating_sub(REORG_WINDOW as usize)..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(blocks: &[(u64, u64)]) -> BTreeMap<BlockNumber, H256> {
+        blocks.iter().map(|&(number, hash)| (number, H256::from_low_u64_be(hash))).collect()
+    }
+
+    fn num_hash(number: u64, hash: u64) -> BlockNumHash {
+        BlockNumHash::new(number, H256::from_low_u64_be(hash))
+    }
+
+    #[test]
+    fn extend() {
+        let recent = chain(&[(1, 1), (2, 2)]);
+        let canonical = chain(&[(1, 1), (2, 2), (3, 3), (4, 4)]);
+
+        let update =
+            diff_heads(&recent, num_hash(2, 2), num_hash(4, 4), |n| Ok(canonical.get(&n).copied()))
+                .unwrap();
+
+        assert!(!update.is_reorg());
+        assert_eq!(update.committed, vec![num_hash(3, 3), num_hash(4, 4)]);
+    }
+
+    #[test]
+    fn reorg() {
+        let recent = chain(&[(1, 1), (2, 2), (3, 3)]);
+        let canonical = chain(&[(1, 1), (2, 20), (3, 30), (4, 40)]);
+
+        let update = diff_heads(&recent, num_hash(3, 3), num_hash(4, 40), |n| {
+            Ok(canonical.get(&n).copied())
+        })
+        .unwrap();
+
+        assert!(update.is_reorg());
+        assert_eq!(update.reverted, vec![num_hash(3, 3), num_hash(2, 2)]);
+        assert_eq!(update.committed, vec![num_hash(2, 20), num_hash(3, 30), num_hash(4, 40)]);
+    }
+
+    #[test]
+    fn reorg_to_shorter_chain() {
+        let recent = chain(&[(1, 1), (2, 2), (3, 3)]);
+        let canonical = chain(&[(1, 1), (2, 20)]);
+
+        let update = diff_heads(&recent, num_hash(3, 3), num_hash(2, 20), |n| {
+            Ok(canonical.get(&n).copied())
+        })
+        .unwrap();
+
+        assert_eq!(update.reverted, vec![num_hash(3, 3), num_hash(2, 2)]);
+        assert_eq!(update.committed, vec![num_hash(2, 20)]);
+    }
+
+    #[test]
+    fn warms_only_the_reorg_window() {
+        let committed: Vec<_> = (1..=REORG_WINDOW + 10).map(|n| num_hash(n, n)).collect();
+
+        let warmed = blocks_to_warm(&committed);
+        assert_eq!(warmed.len(), REORG_WINDOW as usize);
+        assert_eq!(warmed.first(), Some(&num_hash(11, 11)));
+
+        assert_eq!(blocks_to_warm(&committed[..2]), &committed[..2]);
+    }
+}
@@ -0,0 +1,284 @@
+//! In-process fork of the chain for repeated simulations.
+//!
+//! A [`ForkSession`] layers a revm [`CacheDB`](reth_revm::db::CacheDB) over the state of a block.
+//! Transactions applied to the session change only the cache, so simulations can build on each
+//! other, be snapshotted and reverted, without touching the database.
+//!
+//! A session reads the database through a read transaction that stays open for as long as the
+//! session lives. [`RethMiddleware::with_fork_at`] bounds the session to a closure that runs on a
+//! blocking thread, [`RethMiddleware::fork_at`] hands it out for the caller to drop.
+
+use crate::{
+    bundle::{bundle_result, next_block_env, transact, BundleTransaction, BundleTransactionResult},
+    call_many::{call_response, call_tx_env, CallResponse},
+    type_conversions::{rpc::state::state_override_into_reth, ToEthers, ToReth},
+    RethMiddleware, RethMiddlewareError,
+};
+use std::fmt;
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        spoof, transaction::eip2718::TypedTransaction, Address as EthersAddress,
+        BlockId as EthersBlockId, Bytes as EthersBytes, H256 as EthersH256, U256 as EthersU256,
+    },
+};
+use reth_primitives::{Address, BlockId, U256};
+use reth_provider::{BlockReaderIdExt, StateProviderBox, StateProviderFactory};
+use reth_revm::{
+    database::{State, SubState},
+    db::DbAccount,
+    primitives::{BlockEnv, CfgEnv, Env, HashMap, ResultAndState, TxEnv},
+    Database, DatabaseCommit, EVM,
+};
+use reth_rpc::eth::{
+    error::{EthApiError, EthResult},
+    revm_utils::apply_state_overrides,
+    EthTransactions,
+};
+
+/// Identifies a snapshot of a [`ForkSession`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SnapshotId(usize);
+
+/// A snapshot of a session.
+///
+/// Instead of a copy of the cache, the snapshot records the accounts changed while it is the
+/// latest snapshot, as they were before their first change. Accounts that were not cached are
+/// recorded as `None`. Code is cached by hash and never changes, so it is not recorded.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    accounts: HashMap<Address, Option<DbAccount>>,
+    transaction_count: usize,
+    log_count: u64,
+}
+
+/// A sandbox over the state of a block.
+///
+/// Transactions are executed in the environment of the block after the forked block, see
+/// [`RethMiddleware::fork_at`]. Every method reads the database synchronously.
+///
+/// The session keeps a read transaction of the database open until it is dropped. While it is
+/// open, the node cannot reuse the pages it frees, so the database file grows, and
+/// [`RethMiddleware::shutdown`] cannot close the database.
+pub struct ForkSession {
+    db: SubState<StateProviderBox>,
+    cfg: CfgEnv,
+    block_env: BlockEnv,
+    block_number: u64,
+    snapshots: Vec<Snapshot>,
+    transaction_count: usize,
+    log_count: u64,
+}
+
+impl fmt::Debug for ForkSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForkSession")
+            .field("block_number", &self.block_number())
+            .field("transaction_count", &self.transaction_count)
+            .field("snapshots", &self.snapshots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ForkSession {
+    fn new(state: StateProviderBox, env: ForkEnv) -> Self {
+        Self {
+            db: SubState::new(State::new(state)),
+            cfg: env.cfg,
+            block_env: env.block_env,
+            block_number: env.block_number,
+            snapshots: Vec::new(),
+            transaction_count: 0,
+            log_count: 0,
+        }
+    }
+
+    /// Returns the number of the forked block, transactions run in the block after it.
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    /// Returns the number of transactions applied to the session.
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_count
+    }
+
+    /// Executes `tx` and keeps its changes.
+    ///
+    /// A failed or invalid transaction is reported in the result, an invalid transaction
+    /// changes nothing. Only transactions that cannot be decoded return an error.
+    pub fn apply_transaction(
+        &mut self,
+        tx: impl Into<BundleTransaction>,
+    ) -> EthResult<BundleTransactionResult> {
+        let (tx_hash, tx) = tx.into().into_tx_env(&self.block_env)?;
+        let env = self.env(tx);
+        let (executed, state) = transact(&mut self.db, env)?;
+        self.record(state.keys().copied());
+        self.db.commit(state);
+        let result = bundle_result(self.transaction_count, tx_hash, executed, &mut self.log_count);
+        self.transaction_count += 1;
+        Ok(result)
+    }
+
+    /// Executes `tx` on the state of the session without keeping its changes.
+    pub fn call(&mut self, tx: &TypedTransaction) -> EthResult<CallResponse> {
+        let mut env = self.env(call_tx_env(tx, &self.block_env));
+        env.cfg.disable_base_fee = true;
+        env.cfg.disable_eip3607 = true;
+
+        let mut evm = EVM::with_env(env);
+        evm.database(&mut self.db);
+        let ResultAndState { result, .. } = evm.transact()?;
+        Ok(call_response(result.into()))
+    }
+
+    /// Overrides the balance, nonce, code or storage of accounts.
+    pub fn override_state(&mut self, state: spoof::State) -> EthResult<()> {
+        let state = state_override_into_reth(state)
+            .map_err(|err| EthApiError::InvalidParams(format!("invalid state override: {err}")))?;
+        self.record(state.keys().copied());
+        apply_state_overrides(state, &mut self.db)
+    }
+
+    pub fn balance(&mut self, address: EthersAddress) -> EthResult<EthersU256> {
+        let account = self.db.basic(address.into_reth())?;
+        Ok(account.map(|account| account.balance).unwrap_or_default().into_ethers())
+    }
+
+    pub fn nonce(&mut self, address: EthersAddress) -> EthResult<u64> {
+        let account = self.db.basic(address.into_reth())?;
+        Ok(account.map(|account| account.nonce).unwrap_or_default())
+    }
+
+    pub fn code(&mut self, address: EthersAddress) -> EthResult<EthersBytes> {
+        let Some(account) = self.db.basic(address.into_reth())? else {
+            return Ok(EthersBytes::default())
+        };
+        let code = match account.code {
+            Some(code) => code,
+            None => self.db.code_by_hash(account.code_hash)?,
+        };
+        Ok(code.original_bytes().into())
+    }
+
+    pub fn storage(&mut self, address: EthersAddress, slot: EthersH256) -> EthResult<EthersH256> {
+        let value = self.db.storage(address.into_reth(), U256::from_be_bytes(slot.0))?;
+        Ok(EthersH256(value.to_be_bytes::<32>()))
+    }
+
+    /// Records the current state, to return to it with [`ForkSession::revert`].
+    pub fn snapshot(&mut self) -> SnapshotId {
+        self.snapshots.push(Snapshot {
+            accounts: HashMap::default(),
+            transaction_count: self.transaction_count,
+            log_count: self.log_count,
+        });
+        SnapshotId(self.snapshots.len() - 1)
+    }
+
+    /// Returns to the state of the snapshot, discarding it and every later snapshot.
+    ///
+    /// Returns false if the snapshot does not exist, e.g. because it was already reverted.
+    pub fn revert(&mut self, id: SnapshotId) -> bool {
+        if id.0 >= self.snapshots.len() {
+            return false
+        }
+
+        // the latest snapshot is undone first, so that the oldest recorded account wins
+        for snapshot in self.snapshots.drain(id.0..).rev() {
+            for (address, account) in snapshot.accounts {
+                match account {
+                    Some(account) => self.db.accounts.insert(address, account),
+                    None => self.db.accounts.remove(&address),
+                };
+            }
+            self.transaction_count = snapshot.transaction_count;
+            self.log_count = snapshot.log_count;
+        }
+        true
+    }
+
+    /// Records the accounts that are about to change in the latest snapshot, unless they
+    /// already changed since it was taken.
+    fn record(&mut self, addresses: impl IntoIterator<Item = Address>) {
+        let Some(snapshot) = self.snapshots.last_mut() else { return };
+        for address in addresses {
+            snapshot
+                .accounts
+                .entry(address)
+                .or_insert_with(|| self.db.accounts.get(&address).cloned());
+        }
+    }
+
+    fn env(&self, tx: TxEnv) -> Env {
+        Env { cfg: self.cfg.clone(), block: self.block_env.clone(), tx }
+    }
+}
+
+/// The environment of a session.
+struct ForkEnv {
+    cfg: CfgEnv,
+    block_env: BlockEnv,
+    block_number: u64,
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Forks the chain at the state after `block`.
+    ///
+    /// Transactions run in the environment of the block after `block`: it is numbered one
+    /// after it, 12 seconds later, with the base fee that follows from the gas used by `block`.
+    ///
+    /// The session keeps a read transaction of the database open until it is dropped, so it
+    /// should be short-lived. [`RethMiddleware::with_fork_at`] drops it once its work is done.
+    pub async fn fork_at<B: Into<EthersBlockId>>(
+        &self,
+        block: B,
+    ) -> Result<ForkSession, RethMiddlewareError<M>> {
+        let (env, at) = self.fork_env(block.into()).await?;
+        let state = self.reth_client().state_by_block_id(at).map_err(EthApiError::from)?;
+        Ok(ForkSession::new(state, env))
+    }
+
+    /// Forks the chain at the state after `block` like [`RethMiddleware::fork_at`] and runs `f`
+    /// on the session on a blocking thread.
+    ///
+    /// The session, and with it the read transaction of the database, is dropped once `f`
+    /// returns.
+    pub async fn with_fork_at<B, F, R>(&self, block: B, f: F) -> Result<R, RethMiddlewareError<M>>
+    where
+        B: Into<EthersBlockId>,
+        F: FnOnce(&mut ForkSession) -> EthResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (env, at) = self.fork_env(block.into()).await?;
+        let result = self
+            .reth_api
+            .spawn_with_state_at_block(at, move |state| f(&mut ForkSession::new(state, env)))
+            .await?;
+        Ok(result)
+    }
+
+    /// Returns the environment of a session forked at `block` and the resolved block.
+    async fn fork_env(
+        &self,
+        block: EthersBlockId,
+    ) -> Result<(ForkEnv, BlockId), RethMiddlewareError<M>> {
+        let (cfg, block_env, at) = self.reth_api.evm_env_at(block.into_reth()).await?;
+        let parent = self
+            .reth_client()
+            .header_by_id(at)
+            .map_err(EthApiError::from)?
+            .ok_or(RethMiddlewareError::BlockNotFound)?;
+        let env = ForkEnv {
+            cfg,
+            block_env: next_block_env(&parent, block_env),
+            block_number: parent.number,
+        };
+        Ok((env, at))
+    }
+}
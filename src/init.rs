@@ -1,27 +1,30 @@
-use eyre::Context;
 use reth_beacon_consensus::BeaconConsensus;
-use reth_blockchain_tree::{
-    externals::TreeExternals, BlockchainTree, BlockchainTreeConfig, ShareableBlockchainTree,
-};
+use reth_blockchain_tree::{externals::TreeExternals, BlockchainTree, ShareableBlockchainTree};
 
-use crate::{noop::NoopNetwork, RethApi, RethDebug, RethFilter, RethMiddleware, RethTrace};
+use crate::{
+    builder::{ConfigError, RethMiddlewareBuilder},
+    chain::ChainError,
+    follower::{
+        HeadFollower, HeadUpdate, DEFAULT_HEAD_POLL_INTERVAL, HEAD_UPDATE_CHANNEL_CAPACITY,
+    },
+    noop::NoopNetwork,
+    shutdown::ShutdownHandle,
+    RethApi, RethDebug, RethFilter, RethMiddleware, RethTrace,
+};
 use ethers::providers::Middleware;
 // Reth
 use reth_db::{
     database::{Database, DatabaseGAT},
-    mdbx::{Env, WriteMap},
+    mdbx::{Env, EnvKind, Error as MdbxError, WriteMap},
     tables,
     transaction::DbTx,
+    version::DB_VERSION,
     DatabaseError,
 };
-use reth_primitives::{constants::ETHEREUM_BLOCK_GAS_LIMIT, DEV, GOERLI, MAINNET, SEPOLIA};
 use reth_provider::{providers::BlockchainProvider, ProviderFactory};
 use reth_revm::Factory;
 use reth_rpc::{
-    eth::{
-        cache::{EthStateCache, EthStateCacheConfig},
-        gas_oracle::{GasPriceOracle, GasPriceOracleConfig},
-    },
+    eth::{cache::EthStateCache, gas_oracle::GasPriceOracle},
     DebugApi, EthApi, EthFilter, TraceApi, TracingCallGuard, TracingCallPool,
 };
 use reth_tasks::TaskManager;
@@ -30,8 +33,13 @@ use reth_transaction_pool::{
     EthTransactionValidator, Pool, TransactionValidationTaskExecutor,
 };
 // Std
-use std::{fmt::Debug, path::Path, sync::Arc};
-use tokio::runtime::Handle;
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::sync::broadcast;
 
 pub type Provider = BlockchainProvider<
     Arc<Env<WriteMap>>,
@@ -44,29 +52,90 @@ pub type RethTxPool = Pool<
     InMemoryBlobStore,
 >;
 
+/// The name of the MDBX data file in a database directory.
+pub const MDBX_DATA_FILE_NAME: &str = "mdbx.dat";
+
+/// The name of the file holding the reth database version in a database directory.
+pub const DB_VERSION_FILE_NAME: &str = "database.version";
+
+/// The reth components backing a [`RethMiddleware`] could not be initialized.
+#[derive(Error, Debug)]
+pub enum InitError {
+    /// The configuration was rejected.
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    /// The database directory does not exist.
+    #[error("database directory {0:?} does not exist")]
+    MissingDirectory(PathBuf),
+
+    /// The database directory does not contain an MDBX data file.
+    #[error("database directory {0:?} does not contain mdbx.dat")]
+    MissingDataFile(PathBuf),
+
+    /// The database directory does not contain a readable reth version file.
+    #[error("could not read database version file {path:?}: {reason}")]
+    InvalidVersionFile { path: PathBuf, reason: String },
+
+    /// The database was written by a reth version with a different database layout.
+    #[error("database version {found} does not match the pinned reth database version {expected}")]
+    VersionMismatch { expected: u64, found: u64 },
+
+    /// The database was written by a libmdbx version that cannot be read.
+    #[error("database at {0:?} was written by an incompatible libmdbx version")]
+    IncompatibleMdbx(PathBuf),
+
+    /// The database files cannot be accessed by this process.
+    #[error("permission denied opening database at {0:?}")]
+    PermissionDenied(PathBuf),
+
+    /// The database does not contain a table of the pinned reth version.
+    #[error("database is missing table {0}")]
+    MissingTable(&'static str),
+
+    /// A table of the database exists but could not be opened.
+    #[error("could not open table {table}: {source}")]
+    OpenTable { table: &'static str, source: MdbxError },
+
+    /// The chain spec could not be resolved.
+    #[error(transparent)]
+    UnsupportedChain(#[from] ChainError),
+
+    /// The thread pool for tracing calls could not be built.
+    #[error("could not build the tracing thread pool: {0}")]
+    ThreadPool(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The blockchain tree could not be built on top of the database.
+    #[error("could not build the blockchain tree: {0}")]
+    Tree(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The database could not be read.
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
 impl<M> RethMiddleware<M>
 where
     M: Middleware,
 {
-    /// Until chain spec is in reth db, we need it as an argument
+    /// Until chain spec is in reth db, we need the chain id as part of the configuration
     pub fn try_new(
-        db_path: &Path,
-        handle: Handle,
-        chain_id: u64,
-    ) -> Result<(RethApi, RethFilter, RethTrace, RethDebug), DatabaseError> {
-        let task_manager = TaskManager::new(handle.clone());
-        let task_executor = task_manager.executor();
+        config: &RethMiddlewareBuilder,
+    ) -> Result<
+        (RethApi, RethFilter, RethTrace, RethDebug, broadcast::Sender<HeadUpdate>, ShutdownHandle),
+        InitError,
+    > {
+        if config.strict_open {
+            check_db_dir(&config.db_path)?;
+        }
+        let db = Arc::new(init_db(&config.db_path)?);
 
-        handle.spawn(task_manager);
+        let task_manager = TaskManager::new(config.handle.clone());
+        let task_executor = task_manager.executor();
+        let shutdown_handle = ShutdownHandle::spawn(&config.handle, task_manager, &db);
 
-        let db = Arc::new(init_db(db_path).unwrap());
-        let chain_spec = match chain_id {
-            1 => MAINNET.clone(),
-            5 => GOERLI.clone(),
-            11155111 => SEPOLIA.clone(),
-            1337 => DEV.clone(),
-            _ => panic!("Unsupported chain id"),
-        };
+        let genesis_hash = view(&db, |tx| tx.get::<tables::CanonicalHeaders>(0))??;
+        let chain_spec = config.chain.resolve(genesis_hash, &config.registered_chains)?;
 
         let tree_externals = TreeExternals::new(
             db.clone(),
@@ -75,23 +144,43 @@ where
             chain_spec.clone(),
         );
 
-        let tree_config = BlockchainTreeConfig::default();
+        let tree_config = config.tree_config.clone();
 
         let (canon_state_notification_sender, _receiver) =
             tokio::sync::broadcast::channel(tree_config.max_reorg_depth() as usize * 2);
 
         let blockchain_tree = ShareableBlockchainTree::new(
-            BlockchainTree::new(tree_externals, canon_state_notification_sender, tree_config, None)
-                .unwrap(),
+            BlockchainTree::new(
+                tree_externals,
+                canon_state_notification_sender.clone(),
+                tree_config,
+                None,
+            )
+            .map_err(|err| InitError::Tree(err.into()))?,
         );
 
         let provider = BlockchainProvider::new(
             ProviderFactory::new(Arc::clone(&db), Arc::clone(&chain_spec)),
             blockchain_tree,
         )
-        .unwrap();
+        .map_err(|err| InitError::Tree(err.into()))?;
 
-        let state_cache = EthStateCache::spawn(provider.clone(), EthStateCacheConfig::default());
+        let state_cache = EthStateCache::spawn_with(
+            provider.clone(),
+            config.state_cache_config.clone(),
+            task_executor.clone(),
+        );
+
+        let (head_update_sender, _receiver) =
+            tokio::sync::broadcast::channel(HEAD_UPDATE_CHANNEL_CAPACITY);
+        let head_follower = HeadFollower::new(
+            db.clone(),
+            provider.clone(),
+            state_cache.clone(),
+            head_update_sender.clone(),
+            DEFAULT_HEAD_POLL_INTERVAL,
+        );
+        task_executor.spawn(Box::pin(head_follower.run()));
 
         let blob_store = InMemoryBlobStore::default();
         let tx_pool = reth_transaction_pool::Pool::eth_pool(
@@ -112,14 +201,14 @@ where
             state_cache.clone(),
             GasPriceOracle::new(
                 provider.clone(),
-                GasPriceOracleConfig::default(),
+                config.gas_oracle_config.clone(),
                 state_cache.clone(),
             ),
-            ETHEREUM_BLOCK_GAS_LIMIT,
-            TracingCallPool::build().unwrap(),
+            config.gas_cap,
+            TracingCallPool::build().map_err(|err| InitError::ThreadPool(err.into()))?,
         );
 
-        let tracing_call_guard = TracingCallGuard::new(10);
+        let tracing_call_guard = TracingCallGuard::new(config.max_tracing_requests);
 
         let reth_trace =
             TraceApi::new(provider.clone(), reth_api.clone(), tracing_call_guard.clone());
@@ -131,10 +220,15 @@ where
             tracing_call_guard,
         );
 
-        let reth_filter =
-            EthFilter::new(provider, tx_pool, state_cache, 1000, Box::new(task_executor));
+        let reth_filter = EthFilter::new(
+            provider,
+            tx_pool,
+            state_cache,
+            config.max_logs_per_response,
+            Box::new(task_executor),
+        );
 
-        Ok((reth_api, reth_filter, reth_trace, reth_debug))
+        Ok((reth_api, reth_filter, reth_trace, reth_debug, head_update_sender, shutdown_handle))
     }
 }
 
@@ -152,20 +246,98 @@ where
     Ok(res)
 }
 
+/// Checks that `path` is a database directory written by the pinned reth version, so that a
+/// wrong path is reported before MDBX attempts to open it.
+pub fn check_db_dir<P: AsRef<Path>>(path: P) -> Result<(), InitError> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+        return Err(InitError::MissingDirectory(path.to_path_buf()))
+    }
+    if !path.join(MDBX_DATA_FILE_NAME).is_file() {
+        return Err(InitError::MissingDataFile(path.to_path_buf()))
+    }
+
+    let version_file = path.join(DB_VERSION_FILE_NAME);
+    let invalid_version_file =
+        |reason: String| InitError::InvalidVersionFile { path: version_file.clone(), reason };
+    let version = std::fs::read_to_string(&version_file)
+        .map_err(|err| invalid_version_file(err.to_string()))?
+        .trim()
+        .parse::<u64>()
+        .map_err(|err| invalid_version_file(err.to_string()))?;
+    if version != DB_VERSION {
+        return Err(InitError::VersionMismatch { expected: DB_VERSION, found: version })
+    }
+
+    Ok(())
+}
+
 /// Opens up an existing database at the specified path.
-pub fn init_db<P: AsRef<Path> + Debug>(path: P) -> eyre::Result<Env<WriteMap>> {
-    let _ = std::fs::create_dir_all(path.as_ref());
-    let db = reth_db::mdbx::Env::<reth_db::mdbx::WriteMap>::open(
-        path.as_ref(),
-        reth_db::mdbx::EnvKind::RO,
-        None,
-    )?;
+///
+/// Nothing is created at `path`, a missing database fails to open.
+pub fn init_db<P: AsRef<Path> + Debug>(path: P) -> Result<Env<WriteMap>, InitError> {
+    let path = path.as_ref();
+    let db = Env::<WriteMap>::open(path, EnvKind::RO, None).map_err(|err| match err {
+        DatabaseError::FailedToOpen(code) => match MdbxError::from_err_code(code) {
+            MdbxError::Access => InitError::PermissionDenied(path.to_path_buf()),
+            MdbxError::VersionMismatch => InitError::IncompatibleMdbx(path.to_path_buf()),
+            _ => InitError::Database(err),
+        },
+        err => InitError::Database(err),
+    })?;
 
     view(&db, |tx| {
         for table in tables::Tables::ALL.iter().map(|table| table.name()) {
-            tx.inner.open_db(Some(table)).wrap_err("Could not open db.").unwrap();
+            tx.inner.open_db(Some(table)).map_err(|err| match err {
+                MdbxError::NotFound => InitError::MissingTable(table),
+                source => InitError::OpenTable { table, source },
+            })?;
         }
-    })?;
+        Ok(())
+    })??;
 
     Ok(db)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn check_testdata_db() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("db");
+        check_db_dir(path).unwrap();
+    }
+
+    #[test]
+    fn check_missing_directory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        assert!(matches!(check_db_dir(&path), Err(InitError::MissingDirectory(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn check_missing_data_file() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(check_db_dir(dir.path()), Err(InitError::MissingDataFile(_))));
+    }
+
+    #[test]
+    fn check_version_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        std::fs::write(path.join(MDBX_DATA_FILE_NAME), []).unwrap();
+        assert!(matches!(check_db_dir(path), Err(InitError::InvalidVersionFile { .. })));
+
+        std::fs::write(path.join(DB_VERSION_FILE_NAME), "latest").unwrap();
+        assert!(matches!(check_db_dir(path), Err(InitError::InvalidVersionFile { .. })));
+
+        std::fs::write(path.join(DB_VERSION_FILE_NAME), (DB_VERSION + 1).to_string()).unwrap();
+        assert!(matches!(
+            check_db_dir(path),
+            Err(InitError::VersionMismatch { found, .. }) if found == DB_VERSION + 1
+        ));
+    }
+}
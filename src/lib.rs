@@ -1,18 +1,31 @@
 // std
-use eyre::Result;
+use builder::RethMiddlewareBuilder;
+use chain::ChainSource;
+use ens::EnsCache;
+use fallback::Fallback;
+use follower::HeadUpdate;
+use init::InitError;
 use noop::NoopNetwork;
+use shutdown::{ShutdownError, ShutdownHandle};
 use std::{fmt::Debug, path::Path, sync::Arc};
 
 // ethers
-use ethers::providers::{Middleware, MiddlewareError};
+use ethers::{
+    providers::{Middleware, MiddlewareError},
+    types::{Bytes as EthersBytes, H256 as EthersH256},
+};
 
 //Reth
 use reth_beacon_consensus::BeaconConsensus;
 use reth_blockchain_tree::ShareableBlockchainTree;
 use reth_db::mdbx::{Env, WriteMap};
+use reth_interfaces::{provider::ProviderError, Error as RethError};
 use reth_provider::providers::BlockchainProvider;
 use reth_revm::Factory;
-use reth_rpc::{eth::error::EthApiError, DebugApi, EthApi, EthFilter, TraceApi};
+use reth_rpc::{
+    eth::error::{EthApiError, RpcInvalidTransactionError},
+    DebugApi, EthApi, EthFilter, TraceApi,
+};
 use reth_transaction_pool::{
     blobstore::InMemoryBlobStore, CoinbaseTipOrdering, EthPooledTransaction,
     EthTransactionValidator, Pool, TransactionValidationTaskExecutor,
@@ -21,11 +34,26 @@ use reth_transaction_pool::{
 use jsonrpsee::types::ErrorObjectOwned;
 use thiserror::Error;
 
+pub mod builder;
+pub mod bundle;
+pub mod call_many;
+pub mod chain;
+pub mod database;
+pub mod ens;
+pub mod fallback;
+pub mod follower;
+pub mod fork;
 pub mod init;
 pub mod middleware;
 pub mod noop;
+pub mod overrides;
+pub mod provider;
+pub mod shadow;
+pub mod shutdown;
+pub mod simulate;
+pub mod stream;
 pub mod type_conversions;
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::broadcast};
 
 pub type RethClient = BlockchainProvider<
     Arc<Env<WriteMap>>,
@@ -50,6 +78,12 @@ pub struct RethMiddleware<M> {
     reth_filter: RethFilter,
     reth_trace: RethTrace,
     reth_debug: RethDebug,
+    head_update_sender: broadcast::Sender<HeadUpdate>,
+    shutdown_handle: ShutdownHandle,
+    max_trace_filter_blocks: u64,
+    max_logs_per_response: usize,
+    ens_cache: Arc<EnsCache>,
+    fallback: Arc<Fallback>,
 }
 
 impl<M: std::fmt::Debug> std::fmt::Debug for RethMiddleware<M> {
@@ -66,11 +100,47 @@ pub enum RethMiddlewareError<M: Middleware> {
 
     /// An error occurred in the Reth API.
     #[error(transparent)]
-    RethApiError(#[from] ErrorObjectOwned),
+    RethApiError(ErrorObjectOwned),
 
     /// An error occurred in the Eth API.
     #[error(transparent)]
-    EthApiError(#[from] EthApiError),
+    EthApiError(EthApiError),
+
+    /// The requested block is not in the database.
+    #[error("block not found")]
+    BlockNotFound,
+
+    /// The requested transaction is not in the database.
+    #[error("transaction not found")]
+    TransactionNotFound,
+
+    /// A block, transaction or receipt requested through the Reth API is not in the database.
+    #[error("resource not found: {0}")]
+    ResourceNotFound(String),
+
+    /// The state of the requested block was pruned.
+    #[error("history pruned: {0}")]
+    HistoryPruned(String),
+
+    /// The execution reverted, with the revert data if there is any.
+    #[error("execution reverted")]
+    ExecutionReverted { data: Option<EthersBytes> },
+
+    /// The execution ran out of gas.
+    #[error("out of gas")]
+    OutOfGas,
+
+    /// The request parameters are invalid.
+    #[error("invalid params: {0}")]
+    InvalidParams(String),
+
+    /// A request limit was exceeded.
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+
+    /// A value could not be converted between its reth and ethers types.
+    #[error("conversion failed: {0}")]
+    Conversion(String),
 
     /// A trace was expected but none was found.
     #[error("Missing trace")]
@@ -78,6 +148,99 @@ pub enum RethMiddlewareError<M: Middleware> {
 
     #[error("Chain Id unavailable")]
     ChainIdUnavailable,
+
+    /// The ENS name has no resolver or record.
+    #[error("ens name not found: {0}")]
+    EnsError(String),
+
+    /// The ENS name of an address does not resolve back to that address.
+    #[error("reverse ens name not pointing to itself: {0}")]
+    EnsNotOwned(String),
+}
+
+/// A transaction of a block that could not be traced.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("failed to trace transaction {tx_hash:?}: {message}")]
+pub struct TraceError {
+    pub tx_hash: EthersH256,
+    /// The error reported by reth.
+    pub message: String,
+}
+
+/// JSON-RPC error codes of reth and [EIP-1474](https://eips.ethereum.org/EIPS/eip-1474).
+const EXECUTION_ERROR_CODE: i32 = 3;
+const INVALID_PARAMS_CODE: i32 = -32602;
+const RESOURCE_NOT_FOUND_CODE: i32 = -32001;
+const LIMIT_EXCEEDED_CODE: i32 = -32005;
+const UNKNOWN_BLOCK_CODE: i32 = -39001;
+/// ethers reads error codes of node responses as `i64`.
+pub(crate) const METHOD_NOT_FOUND_CODE: i64 = -32601;
+
+impl<M: Middleware> RethMiddlewareError<M> {
+    /// Whether the request went to the inner provider, which does not support the method.
+    ///
+    /// This is the case for every method of a [`provider::RethProvider`] that cannot be served
+    /// from the database, and for methods a connected node does not implement.
+    pub fn is_unsupported(&self) -> bool {
+        self.as_error_response().map_or(false, |err| err.code == METHOD_NOT_FOUND_CODE)
+    }
+}
+
+impl<M: Middleware> From<EthApiError> for RethMiddlewareError<M> {
+    fn from(err: EthApiError) -> Self {
+        match err {
+            EthApiError::UnknownBlockNumber | EthApiError::UnknownSafeOrFinalizedBlock => {
+                RethMiddlewareError::BlockNotFound
+            }
+            // the block may exist while the transaction index is out of range
+            err @ EthApiError::UnknownBlockOrTxIndex => {
+                RethMiddlewareError::ResourceNotFound(err.to_string())
+            }
+            EthApiError::TransactionNotFound => RethMiddlewareError::TransactionNotFound,
+            EthApiError::InvalidTransaction(
+                RpcInvalidTransactionError::BasicOutOfGas(_) |
+                RpcInvalidTransactionError::MemoryOutOfGas(_) |
+                RpcInvalidTransactionError::PrecompileOutOfGas(_) |
+                RpcInvalidTransactionError::InvalidOperandOutOfGas(_),
+            ) => RethMiddlewareError::OutOfGas,
+            // the revert data is only exposed by the JSON-RPC error
+            err @ EthApiError::InvalidTransaction(RpcInvalidTransactionError::Revert(_)) => {
+                ErrorObjectOwned::from(err).into()
+            }
+            EthApiError::InvalidParams(message) => RethMiddlewareError::InvalidParams(message),
+            err @ (EthApiError::InvalidBlockRange |
+            EthApiError::ConflictingFeeFieldsInRequest |
+            EthApiError::BothStateAndStateDiffInOverride(_) |
+            EthApiError::InvalidTracerConfig |
+            EthApiError::FailedToDecodeSignedTransaction |
+            EthApiError::InvalidTransactionSignature |
+            EthApiError::InvalidRewardPercentiles) => {
+                RethMiddlewareError::InvalidParams(err.to_string())
+            }
+            err @ EthApiError::Internal(RethError::Provider(ProviderError::StateAtBlockPruned(
+                _,
+            ))) => RethMiddlewareError::HistoryPruned(err.to_string()),
+            err => RethMiddlewareError::EthApiError(err),
+        }
+    }
+}
+
+impl<M: Middleware> From<ErrorObjectOwned> for RethMiddlewareError<M> {
+    fn from(err: ErrorObjectOwned) -> Self {
+        match err.code() {
+            EXECUTION_ERROR_CODE => RethMiddlewareError::ExecutionReverted {
+                data: err.data().and_then(|data| serde_json::from_str(data.get()).ok()),
+            },
+            INVALID_PARAMS_CODE => RethMiddlewareError::InvalidParams(err.message().to_string()),
+            LIMIT_EXCEEDED_CODE => RethMiddlewareError::RateLimited(err.message().to_string()),
+            UNKNOWN_BLOCK_CODE => RethMiddlewareError::BlockNotFound,
+            // the code is shared by blocks, transactions and receipts
+            RESOURCE_NOT_FOUND_CODE => {
+                RethMiddlewareError::ResourceNotFound(err.message().to_string())
+            }
+            _ => RethMiddlewareError::RethApiError(err),
+        }
+    }
 }
 
 impl<M: Middleware> MiddlewareError for RethMiddlewareError<M> {
@@ -99,18 +262,127 @@ impl<M> RethMiddleware<M>
 where
     M: Middleware,
 {
-    pub fn new<P: AsRef<Path>>(
+    /// Opens the database at `db_path` with the default limits.
+    ///
+    /// `chain` is a chain id of a chain known to reth, a chain spec or a genesis file, see
+    /// [`ChainSource`]. See [`RethMiddleware::builder`] to tune the reth internals.
+    pub fn new<P: AsRef<Path>, C: Into<ChainSource>>(
+        inner: M,
+        db_path: P,
+        handle: Handle,
+        chain: C,
+    ) -> Result<Self, InitError> {
+        RethMiddlewareBuilder::new(db_path, handle, chain).build(inner)
+    }
+
+    /// Opens the database at `db_path` with the default limits, selecting the chain spec by the
+    /// genesis hash of the database.
+    pub fn with_detected_chain<P: AsRef<Path>>(
         inner: M,
         db_path: P,
         handle: Handle,
-        chain_id: u64,
-    ) -> Result<Self> {
-        let (reth_api, reth_filter, reth_trace, reth_debug) =
-            Self::try_new(db_path.as_ref(), handle, chain_id)?;
-        Ok(Self { inner, reth_api, reth_filter, reth_trace, reth_debug })
+    ) -> Result<Self, InitError> {
+        Self::new(inner, db_path, handle, ChainSource::Detect)
+    }
+
+    /// Returns a builder for the database at `db_path`.
+    pub fn builder<P: AsRef<Path>, C: Into<ChainSource>>(
+        db_path: P,
+        handle: Handle,
+        chain: C,
+    ) -> RethMiddlewareBuilder {
+        RethMiddlewareBuilder::new(db_path, handle, chain)
     }
 
     pub fn reth_api(&self) -> &RethApi {
         &self.reth_api
     }
+
+    /// Returns the client of the local database.
+    pub fn reth_client(&self) -> &RethClient {
+        self.reth_api.provider()
+    }
+
+    /// Returns the handle that shuts down the background tasks of this middleware and its clones.
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        &self.shutdown_handle
+    }
+
+    /// Shuts down the background tasks and waits for them to stop.
+    ///
+    /// If no other clone of this middleware is alive, this also waits until the database is
+    /// closed, so that it can be reopened, and fails if it is still held after
+    /// [`DEFAULT_DB_CLOSE_TIMEOUT`](shutdown::DEFAULT_DB_CLOSE_TIMEOUT). Dropping the last clone
+    /// shuts down the tasks without waiting.
+    pub async fn shutdown(self) -> Result<(), ShutdownError> {
+        let shutdown_handle = self.shutdown_handle.clone();
+        drop(self);
+        shutdown_handle.shutdown().await
+    }
+
+    /// Subscribes to changes of the canonical head written to the database by the reth node.
+    pub fn subscribe_head_updates(&self) -> broadcast::Receiver<HeadUpdate> {
+        self.head_update_sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::Provider;
+    use provider::OfflineClient;
+
+    type Error = RethMiddlewareError<Provider<OfflineClient>>;
+
+    #[test]
+    fn eth_api_errors() {
+        assert!(matches!(Error::from(EthApiError::UnknownBlockNumber), Error::BlockNotFound));
+        assert!(matches!(
+            Error::from(EthApiError::UnknownBlockOrTxIndex),
+            Error::ResourceNotFound(_)
+        ));
+        assert!(matches!(
+            Error::from(EthApiError::TransactionNotFound),
+            Error::TransactionNotFound
+        ));
+        assert!(matches!(
+            Error::from(EthApiError::InvalidTransaction(
+                RpcInvalidTransactionError::MemoryOutOfGas(Default::default())
+            )),
+            Error::OutOfGas
+        ));
+        assert!(matches!(Error::from(EthApiError::InvalidBlockRange), Error::InvalidParams(_)));
+        assert!(matches!(
+            Error::from(EthApiError::Internal(ProviderError::StateAtBlockPruned(1).into())),
+            Error::HistoryPruned(_)
+        ));
+    }
+
+    #[test]
+    fn rpc_errors() {
+        let revert =
+            ErrorObjectOwned::owned(EXECUTION_ERROR_CODE, "execution reverted", Some("0x01"));
+        assert!(matches!(
+            Error::from(revert),
+            Error::ExecutionReverted { data: Some(data) } if data == EthersBytes::from(vec![1])
+        ));
+
+        let limit = ErrorObjectOwned::owned(LIMIT_EXCEEDED_CODE, "too many requests", None::<()>);
+        assert!(matches!(Error::from(limit), Error::RateLimited(_)));
+
+        let unknown_block =
+            ErrorObjectOwned::owned(UNKNOWN_BLOCK_CODE, "unknown block", None::<()>);
+        assert!(matches!(Error::from(unknown_block), Error::BlockNotFound));
+
+        let not_found =
+            ErrorObjectOwned::owned(RESOURCE_NOT_FOUND_CODE, "receipt not found", None::<()>);
+        assert!(matches!(Error::from(not_found), Error::ResourceNotFound(_)));
+
+        // errors are not classified by their message
+        let pruned = ErrorObjectOwned::owned(-32000, "state at block #1 is pruned", None::<()>);
+        assert!(matches!(Error::from(pruned), Error::RethApiError(_)));
+
+        let other = ErrorObjectOwned::owned(-32000, "internal error", None::<()>);
+        assert!(matches!(Error::from(other), Error::RethApiError(_)));
+    }
 }
@@ -1,12 +1,20 @@
 use crate::{
-    type_conversions::{ToEthers, ToReth},
-    RethMiddleware, RethMiddlewareError,
+    fallback::never_missing,
+    overrides::CallOverrides,
+    type_conversions::{
+        rpc::trace::{tracing_call_options_into_reth, UnresolvedTraceFilter},
+        ToEthers, ToReth,
+    },
+    RethMiddleware, RethMiddlewareError, TraceError,
 };
+use std::convert::identity;
+
 use async_trait::async_trait;
+use jsonrpsee::core::RpcResult;
 
 // Ether rs Types
 use ethers::{
-    providers::{Middleware, MiddlewareError},
+    providers::Middleware,
     types::{
         transaction::{
             eip2718::TypedTransaction,
@@ -18,23 +26,27 @@ use ethers::{
         EIP1186ProofResponse as EthersEIP1186ProofResponse, FeeHistory as EthersFeeHistory,
         Filter as EthersFilter, GethDebugTracingCallOptions as EthersDebugTracingCallOptions,
         GethDebugTracingOptions as EthersDebugTracingOptions, GethTrace as EthersGethTrace,
-        Log as EthersLog, NameOrAddress, Trace as EthersTrace, TraceType as EthersTraceType,
-        Transaction as EthersTransaction, TransactionReceipt as EthersTransactionReceipt,
-        TxHash as EthersTxHash, H256 as EthersH256, U256 as EthersU256, U64 as EthersU64,
+        Log as EthersLog, NameOrAddress, Trace as EthersTrace, TraceFilter as EthersTraceFilter,
+        TraceType as EthersTraceType, Transaction as EthersTransaction,
+        TransactionReceipt as EthersTransactionReceipt, TxHash as EthersTxHash, H256 as EthersH256,
+        U256 as EthersU256, U64 as EthersU64,
     },
 };
 
 // Reth Types
-use reth_primitives::BlockId;
-use reth_rpc::eth::revm_utils::EvmOverrides;
+use reth_primitives::{Address, BlockId, BlockNumberOrTag};
+use reth_provider::BlockIdReader;
+use reth_rpc::eth::error::EthApiError;
 use reth_rpc_api::{EthApiServer, EthFilterApiServer};
 // use reth_rpc_types::trace::geth::TraceResult;
 use reth_rpc_types::{
     trace::{
         common::TraceResult,
+        filter::TraceFilter,
         geth::{DefaultFrame, GethTrace},
+        parity::{Action, LocalizedTransactionTrace, TraceOutput},
     },
-    Filter,
+    Filter, RichBlock,
 };
 
 impl<M> RethMiddleware<M>
@@ -46,12 +58,185 @@ where
         who: T,
     ) -> Result<EthersAddress, RethMiddlewareError<M>> {
         match who.into() {
-            NameOrAddress::Name(ens_name) => {
-                self.inner.resolve_name(&ens_name).await.map_err(RethMiddlewareError::from_err)
-            }
+            NameOrAddress::Name(ens_name) => self.resolve_ens_name(&ens_name).await,
             NameOrAddress::Address(addr) => Ok(addr),
         }
     }
+
+    /// Returns all receipts for a block, which can be given as a tag, a number or a hash.
+    ///
+    /// Returns [`RethMiddlewareError::BlockNotFound`] if the block is not known to the local
+    /// database.
+    pub async fn get_block_receipts_by_id<T: Into<EthersBlockId>>(
+        &self,
+        block: T,
+    ) -> Result<Vec<EthersTransactionReceipt>, RethMiddlewareError<M>> {
+        let block: EthersBlockId = block.into();
+        let block_id: BlockId = block.into_reth();
+        let receipts = self
+            .reth_api
+            .block_receipts(block_id)
+            .await?
+            .ok_or(RethMiddlewareError::BlockNotFound)?;
+
+        Ok(receipts.into_ethers())
+    }
+
+    /// Reads a block by hash or number, with its full transactions if `full` is set.
+    async fn rich_block(&self, block: EthersBlockId, full: bool) -> RpcResult<Option<RichBlock>> {
+        match block {
+            EthersBlockId::Hash(hash) => self.reth_api.block_by_hash(hash.into(), full).await,
+            EthersBlockId::Number(num) => {
+                self.reth_api.block_by_number(num.into_reth(), full).await
+            }
+        }
+    }
+
+    /// Traces every transaction of a block, keeping the error of each transaction that could not
+    /// be traced rather than replacing it with an empty trace.
+    pub async fn debug_trace_block_results<T: Into<EthersBlockId>>(
+        &self,
+        block: T,
+        trace_options: EthersDebugTracingOptions,
+    ) -> Result<Vec<Result<EthersGethTrace, TraceError>>, RethMiddlewareError<M>> {
+        let block: EthersBlock<EthersTxHash> = self
+            .rich_block(block.into(), false)
+            .await?
+            .into_ethers()
+            .ok_or(RethMiddlewareError::BlockNotFound)?;
+        let block_hash = block.hash.ok_or(RethMiddlewareError::BlockNotFound)?;
+
+        // trace by hash, so that the traces match the transactions of the block even if a tag
+        // moved to another block meanwhile
+        let traces = self
+            .reth_debug
+            .debug_trace_block(
+                EthersBlockId::Hash(block_hash).into_reth(),
+                trace_options.into_reth(),
+            )
+            .await?;
+        // every transaction must be matched with its own trace
+        if traces.len() != block.transactions.len() {
+            return Err(RethMiddlewareError::MissingTrace)
+        }
+
+        Ok(block
+            .transactions
+            .into_iter()
+            .zip(traces)
+            .map(|(tx_hash, trace)| match trace {
+                TraceResult::Success { result } => Ok(result.into_ethers()),
+                TraceResult::Error { error } => Err(TraceError { tx_hash, message: error }),
+            })
+            .collect())
+    }
+
+    /// Walks the filter's block range and collects the matching traces of every block, applying
+    /// the `after`/`count` pagination on the way.
+    ///
+    /// Tags in the range are resolved against the database, a missing bound is the latest
+    /// block. The range may span at most `max_trace_filter_blocks` blocks.
+    async fn filter_traces(
+        &self,
+        filter: EthersTraceFilter,
+    ) -> Result<Vec<LocalizedTransactionTrace>, RethMiddlewareError<M>> {
+        let UnresolvedTraceFilter { from_block, to_block, filter } =
+            filter.try_into().map_err(|err| {
+                RethMiddlewareError::Conversion(format!("invalid trace filter: {err}"))
+            })?;
+        let from_block = self.resolve_block_number(from_block)?;
+        let to_block = self.resolve_block_number(to_block)?;
+        if from_block > to_block {
+            return Err(EthApiError::InvalidBlockRange.into())
+        }
+        let blocks = (to_block - from_block).saturating_add(1);
+        if blocks > self.max_trace_filter_blocks {
+            return Err(RethMiddlewareError::InvalidParams(format!(
+                "trace filter spans {blocks} blocks, more than the limit of {}",
+                self.max_trace_filter_blocks
+            )))
+        }
+
+        let mut skip = filter.after.unwrap_or_default() as usize;
+        let count = filter.count.map(|count| count as usize).unwrap_or(usize::MAX);
+        let mut traces = Vec::new();
+
+        for block_number in from_block..=to_block {
+            let block_id = BlockId::Number(BlockNumberOrTag::Number(block_number));
+            let block_traces = self.reth_trace.trace_block(block_id).await?.unwrap_or_default();
+
+            for trace in block_traces {
+                if !trace_matches(&filter, &trace) {
+                    continue
+                }
+                if skip > 0 {
+                    skip -= 1;
+                    continue
+                }
+                traces.push(trace);
+                if traces.len() == count {
+                    return Ok(traces)
+                }
+            }
+        }
+
+        Ok(traces)
+    }
+
+    /// Resolves a block number or tag to a number against the database, `None` being the latest
+    /// block.
+    pub(crate) fn resolve_block_number(
+        &self,
+        block: Option<EthersBlockNumber>,
+    ) -> Result<u64, RethMiddlewareError<M>> {
+        let block: BlockNumberOrTag =
+            block.map(ToReth::into_reth).unwrap_or(BlockNumberOrTag::Latest);
+        self.reth_client()
+            .convert_block_number(block)
+            .map_err(EthApiError::from)?
+            .ok_or(RethMiddlewareError::BlockNotFound)
+    }
+}
+
+/// Converts a trace, failing for traces of pending transactions.
+fn localized_trace<M: Middleware>(
+    trace: LocalizedTransactionTrace,
+) -> Result<EthersTrace, RethMiddlewareError<M>> {
+    trace
+        .into_ethers()
+        .ok_or_else(|| RethMiddlewareError::Conversion("trace is not in a block".to_string()))
+}
+
+fn localized_traces<M: Middleware>(
+    traces: Vec<LocalizedTransactionTrace>,
+) -> Result<Vec<EthersTrace>, RethMiddlewareError<M>> {
+    traces.into_iter().map(localized_trace).collect()
+}
+
+/// Returns true if the trace's sender and recipient pass the filter's address constraints.
+///
+/// An empty address list matches every trace.
+fn trace_matches(filter: &TraceFilter, trace: &LocalizedTransactionTrace) -> bool {
+    let (from, to): (Option<Address>, Option<Address>) = match &trace.trace.action {
+        Action::Call(call) => (Some(call.from), Some(call.to)),
+        Action::Create(create) => {
+            let created = match &trace.trace.result {
+                Some(TraceOutput::Create(output)) => Some(output.address),
+                _ => None,
+            };
+            (Some(create.from), created)
+        }
+        Action::Selfdestruct(selfdestruct) => {
+            (Some(selfdestruct.address), Some(selfdestruct.refund_address))
+        }
+        Action::Reward(reward) => (None, Some(reward.author)),
+    };
+
+    let matches = |addresses: &[Address], address: Option<Address>| {
+        addresses.is_empty() || address.map_or(false, |address| addresses.contains(&address))
+    };
+
+    matches(&filter.from_address, from) && matches(&filter.to_address, to)
 }
 
 #[async_trait]
@@ -73,10 +258,8 @@ where
         tx: &TypedTransaction,
         block: Option<EthersBlockId>,
     ) -> Result<EthersBytes, Self::Error> {
-        let call_request = tx.into_reth();
-        let block_id = block.into_reth();
-
-        Ok(self.reth_api.call(call_request, block_id, EvmOverrides::default()).await?.into_ethers())
+        let local = self.call_with_overrides(tx, block, Default::default());
+        self.with_fallback("call", local, identity, never_missing, self.inner.call(tx, block)).await
     }
 
     async fn estimate_gas(
@@ -84,10 +267,9 @@ where
         tx: &TypedTransaction,
         block: Option<EthersBlockId>,
     ) -> Result<EthersU256, Self::Error> {
-        let call_request = tx.into_reth();
-        let block_id = block.into_reth();
-
-        Ok(self.reth_api.estimate_gas(call_request, block_id).await?.into())
+        let local = self.estimate_gas_with_overrides(tx, block, CallOverrides::default());
+        let inner = self.inner.estimate_gas(tx, block);
+        self.with_fallback("estimate_gas", local, identity, never_missing, inner).await
     }
 
     async fn create_access_list(
@@ -95,12 +277,9 @@ where
         tx: &TypedTransaction,
         block: Option<EthersBlockId>,
     ) -> Result<EthersAccessListWithGasUsed, Self::Error> {
-        let call_request = tx.into_reth();
-        let block_id = block.into_reth();
-
-        let result = self.reth_api.create_access_list(call_request, block_id).await?;
-
-        Ok(result.into_ethers())
+        let local = self.create_access_list_with_overrides(tx, block, CallOverrides::default());
+        let inner = self.inner.create_access_list(tx, block);
+        self.with_fallback("create_access_list", local, identity, never_missing, inner).await
     }
 
     // State related methods
@@ -119,7 +298,9 @@ where
         let block_id = block.into_reth();
 
         // call `storage_at` and convert the result
-        Ok(self.reth_api.storage_at(from.into(), index, block_id).await?.into())
+        let local = self.reth_api.storage_at(from.into(), index, block_id);
+        let inner = self.inner.get_storage_at(from, location, block);
+        self.with_fallback("get_storage_at", local, Into::into, never_missing, inner).await
     }
 
     async fn get_code<T: Into<NameOrAddress> + Send + Sync>(
@@ -129,10 +310,9 @@ where
     ) -> Result<EthersBytes, Self::Error> {
         let at = self.get_address(at).await?;
 
-        let block_id = block.into_reth();
-        let code = self.reth_api.get_code(at.into(), block_id).await?;
-        // Convert to EthersBytes
-        Ok(code.into_ethers())
+        let local = self.reth_api.get_code(at.into(), block.into_reth());
+        let inner = self.inner.get_code(at, block);
+        self.with_fallback("get_code", local, ToEthers::into_ethers, never_missing, inner).await
     }
 
     async fn get_balance<T: Into<NameOrAddress> + Send + Sync>(
@@ -141,7 +321,9 @@ where
         block: Option<EthersBlockId>,
     ) -> Result<EthersU256, Self::Error> {
         let from = self.get_address(from).await?;
-        Ok(self.reth_api.balance(from.into(), block.into_reth()).await?.into())
+        let local = self.reth_api.balance(from.into(), block.into_reth());
+        let inner = self.inner.get_balance(from, block);
+        self.with_fallback("get_balance", local, Into::into, never_missing, inner).await
     }
 
     async fn get_proof<T: Into<NameOrAddress> + Send + Sync>(
@@ -176,6 +358,20 @@ where
             .into_ethers())
     }
 
+    // ENS
+
+    async fn resolve_name(&self, ens_name: &str) -> Result<EthersAddress, Self::Error> {
+        self.resolve_ens_name(ens_name).await
+    }
+
+    async fn lookup_address(&self, address: EthersAddress) -> Result<String, Self::Error> {
+        self.lookup_ens_address(address).await
+    }
+
+    async fn resolve_field(&self, ens_name: &str, field: &str) -> Result<String, Self::Error> {
+        self.resolve_ens_field(ens_name, field).await
+    }
+
     // Chain Info
 
     async fn get_chainid(&self) -> Result<EthersU256, RethMiddlewareError<M>> {
@@ -192,19 +388,15 @@ where
         Ok(self.reth_api.block_number()?.into_ethers())
     }
 
-    /*async fn get_block_receipts<T: Into<EthersBlockNumber> + Send + Sync>(
+    async fn get_block_receipts<T: Into<EthersBlockNumber> + Send + Sync>(
         &self,
         block: T,
     ) -> Result<Vec<EthersTransactionReceipt>, Self::Error> {
-        let block = block.into();
-        let receipts = self
-            .reth_api
-            .block_receipts(block)
-            .await?;
-
-        Ok(receipts)
+        let block: EthersBlockNumber = block.into();
+        let local = self.get_block_receipts_by_id(block);
+        let inner = self.inner.get_block_receipts(block);
+        self.with_fallback("get_block_receipts", local, identity, never_missing, inner).await
     }
-    */
 
     // Transaction
 
@@ -212,13 +404,11 @@ where
         &self,
         transaction_hash: T,
     ) -> Result<Option<EthersTransaction>, Self::Error> {
-        let maybe_transaction =
-            self.reth_api.transaction_by_hash(transaction_hash.into().into()).await?;
-
-        match maybe_transaction {
-            Some(reth_tx) => Ok(Some(reth_tx.into_ethers())),
-            None => Ok(None),
-        }
+        let transaction_hash: EthersTxHash = transaction_hash.into();
+        let local = self.reth_api.transaction_by_hash(transaction_hash.into());
+        let inner = self.inner.get_transaction(transaction_hash);
+        self.with_fallback("get_transaction", local, ToEthers::into_ethers, Option::is_none, inner)
+            .await
     }
 
     async fn get_transaction_receipt<T: Send + Sync + Into<EthersTxHash>>(
@@ -226,14 +416,16 @@ where
         transaction_hash: T,
     ) -> Result<Option<EthersTransactionReceipt>, RethMiddlewareError<M>> {
         let hash = ethers::types::H256::from_slice(transaction_hash.into().as_bytes());
-        let receipt = self.reth_api.transaction_receipt(hash.into()).await?;
-        match receipt {
-            Some(receipt) => {
-                let receipt = receipt.into_ethers();
-                Ok(Some(receipt))
-            }
-            None => Ok(None),
-        }
+        let local = self.reth_api.transaction_receipt(hash.into());
+        let inner = self.inner.get_transaction_receipt(hash);
+        self.with_fallback(
+            "get_transaction_receipt",
+            local,
+            ToEthers::into_ethers,
+            Option::is_none,
+            inner,
+        )
+        .await
     }
 
     async fn get_transaction_count<T: Into<NameOrAddress> + Send + Sync>(
@@ -243,8 +435,9 @@ where
     ) -> Result<EthersU256, Self::Error> {
         let from = self.get_address(from).await?;
 
-        let block_id = block.into_reth();
-        Ok(self.reth_api.transaction_count(from.into(), block_id).await?.into())
+        let local = self.reth_api.transaction_count(from.into(), block.into_reth());
+        let inner = self.inner.get_transaction_count(from, block);
+        self.with_fallback("get_transaction_count", local, Into::into, never_missing, inner).await
     }
 
     // Blocks
@@ -255,14 +448,9 @@ where
     ) -> Result<Option<EthersBlock<EthersH256>>, Self::Error> {
         let block_id: EthersBlockId = block_hash_or_number.into();
 
-        let block = match block_id {
-            EthersBlockId::Hash(hash) => self.reth_api.block_by_hash(hash.into(), false).await?,
-            EthersBlockId::Number(num) => {
-                self.reth_api.block_by_number(num.into_reth(), false).await?
-            }
-        };
-
-        Ok(block.into_ethers())
+        let local = self.rich_block(block_id, false);
+        let inner = self.inner.get_block(block_id);
+        self.with_fallback("get_block", local, ToEthers::into_ethers, Option::is_none, inner).await
     }
 
     async fn get_uncle<T: Into<EthersBlockId> + Send + Sync>(
@@ -292,16 +480,18 @@ where
         &self,
         block_hash_or_number: T,
     ) -> Result<Option<EthersBlock<EthersTransaction>>, Self::Error> {
-        let block_id = block_hash_or_number.into();
-
-        let block = match block_id {
-            EthersBlockId::Hash(hash) => self.reth_api.block_by_hash(hash.into(), true).await?,
-            EthersBlockId::Number(num) => {
-                self.reth_api.block_by_number(num.into_reth(), true).await?
-            }
-        };
+        let block_id: EthersBlockId = block_hash_or_number.into();
 
-        Ok(block.into_ethers())
+        let local = self.rich_block(block_id, true);
+        let inner = self.inner.get_block_with_txs(block_id);
+        self.with_fallback(
+            "get_block_with_txs",
+            local,
+            ToEthers::into_ethers,
+            Option::is_none,
+            inner,
+        )
+        .await
     }
 
     // Logs
@@ -312,8 +502,6 @@ where
         Ok(reth_logs.into_ethers())
     }
 
-    //TODO: Implement get_logs_paginated
-    //TODO: Implement stream event logs (watch)
     //TODO: Watch pending tx
 
     // Tracing
@@ -374,13 +562,20 @@ where
             .reth_trace
             .replay_block_transactions(BlockId::Number(block.into_reth()), trace_type.into_reth())
             .await?;
-        Ok(res.unwrap().into_ethers())
+        Ok(res.ok_or(RethMiddlewareError::BlockNotFound)?.into_ethers())
     }
 
     async fn trace_block(&self, block: EthersBlockNumber) -> Result<Vec<EthersTrace>, Self::Error> {
         let block_id = block.into_reth();
         let trace_opt = self.reth_trace.trace_block(BlockId::Number(block_id)).await?;
-        Ok(trace_opt.ok_or(RethMiddlewareError::MissingTrace)?.into_ethers())
+        localized_traces(trace_opt.ok_or(RethMiddlewareError::BlockNotFound)?)
+    }
+
+    async fn trace_filter(
+        &self,
+        filter: EthersTraceFilter,
+    ) -> Result<Vec<EthersTrace>, Self::Error> {
+        localized_traces(self.filter_traces(filter).await?)
     }
 
     async fn debug_trace_transaction(
@@ -428,7 +623,7 @@ where
     ) -> Result<Vec<EthersGethTrace>, Self::Error> {
         let mut debug_trace = self
             .reth_debug
-            .debug_trace_block(block.unwrap().into_reth(), trace_options.into_reth())
+            .debug_trace_block(block.unwrap_or_default().into_reth(), trace_options.into_reth())
             .await?;
 
         let mut trace = vec![];
@@ -449,13 +644,12 @@ where
         block_id: Option<EthersBlockId>,
         trace_options: EthersDebugTracingCallOptions,
     ) -> Result<EthersGethTrace, Self::Error> {
+        let trace_options = tracing_call_options_into_reth(trace_options).map_err(|err| {
+            RethMiddlewareError::Conversion(format!("invalid tracing options: {err}"))
+        })?;
         let debug_trace = self
             .reth_debug
-            .debug_trace_call(
-                call.into().into_reth(),
-                block_id.into_reth(),
-                trace_options.into_reth(),
-            )
+            .debug_trace_call(call.into().into_reth(), block_id.into_reth(), trace_options)
             .await?;
 
         Ok(debug_trace.into_ethers())
@@ -467,7 +661,8 @@ where
         index: Vec<T>,
     ) -> Result<EthersTrace, Self::Error> {
         let index: Vec<usize> = index.into_iter().map(|i| i.into().as_usize()).collect();
-        Ok(self.reth_trace.trace_get(hash.into(), index).await?.into_ethers().unwrap())
+        let trace = self.reth_trace.trace_get(hash.into(), index).await?;
+        localized_trace(trace.ok_or(RethMiddlewareError::MissingTrace)?)
     }
 
     async fn trace_transaction(
@@ -475,6 +670,6 @@ where
         tx_hash: EthersTxHash,
     ) -> Result<Vec<EthersTrace>, Self::Error> {
         let trace = self.reth_trace.trace_transaction(tx_hash.into()).await?;
-        Ok(trace.into_ethers())
+        localized_traces(trace.ok_or(RethMiddlewareError::TransactionNotFound)?)
     }
 }
@@ -0,0 +1,279 @@
+//! Calls simulated against modified state and block environments.
+//!
+//! [`CallOverrides`] is the ethers counterpart of reth's [`EvmOverrides`]: accounts are
+//! overridden with [`spoof::State`] and the block environment with [`BlockOverrides`].
+
+use crate::{
+    bundle::{ExecutionFailure, ExecutionOutcome},
+    type_conversions::{rpc::state::state_override_into_reth, ToEthers, ToReth},
+    RethMiddleware, RethMiddlewareError,
+};
+use ethers::{
+    providers::Middleware,
+    types::{
+        spoof,
+        transaction::{
+            eip2718::TypedTransaction,
+            eip2930::AccessListWithGasUsed as EthersAccessListWithGasUsed,
+        },
+        BlockId as EthersBlockId, BlockOverrides, Bytes as EthersBytes, U256 as EthersU256,
+    },
+    utils::get_contract_address,
+};
+use jsonrpsee::types::ErrorObjectOwned;
+use reth_primitives::{AccessListWithGasUsed, Address, BlockId, BlockNumberOrTag, U256};
+use reth_revm::{
+    access_list::AccessListInspector,
+    primitives::{ExecutionResult, Halt, ResultAndState, TransactTo},
+    Database, EVM,
+};
+use reth_rpc::eth::{error::EthApiError, revm_utils::EvmOverrides, EthTransactions};
+use reth_rpc_types::{state::StateOverride, CallRequest};
+
+/// The JSON-RPC error code of a halted execution, see
+/// [EIP-1474](https://eips.ethereum.org/EIPS/eip-1474).
+const VM_EXECUTION_ERROR_CODE: i32 = -32015;
+
+/// The highest precompile address, up to the point evaluation precompile of Cancun.
+///
+/// Precompiles are never part of an access list.
+const MAX_PRECOMPILE: u64 = 0x0a;
+
+/// State and block overrides applied to a simulated call.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOverrides {
+    /// Replaces the balance, nonce, code or storage of accounts.
+    pub state: Option<spoof::State>,
+    /// Replaces fields of the block environment.
+    pub block: Option<BlockOverrides>,
+}
+
+impl CallOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the state overrides.
+    pub fn state(mut self, state: spoof::State) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Sets the block overrides.
+    pub fn block(mut self, block: BlockOverrides) -> Self {
+        self.block = Some(block);
+        self
+    }
+
+    /// Returns true if neither the state nor the block environment is overridden.
+    pub fn is_empty(&self) -> bool {
+        self.state.is_none() && self.block.is_none()
+    }
+
+    /// Converts the overrides into reth's [`EvmOverrides`].
+    pub(crate) fn try_into_reth<M: Middleware>(
+        self,
+    ) -> Result<EvmOverrides, RethMiddlewareError<M>> {
+        Ok(EvmOverrides::new(
+            self.state.map(state_overrides::<M>).transpose()?,
+            self.block.map(|block| Box::new(block.into_reth())),
+        ))
+    }
+}
+
+impl From<spoof::State> for CallOverrides {
+    fn from(state: spoof::State) -> Self {
+        Self::new().state(state)
+    }
+}
+
+impl From<BlockOverrides> for CallOverrides {
+    fn from(block: BlockOverrides) -> Self {
+        Self::new().block(block)
+    }
+}
+
+/// Converts ethers state overrides into reth's [`StateOverride`].
+pub(crate) fn state_overrides<M: Middleware>(
+    state: spoof::State,
+) -> Result<StateOverride, RethMiddlewareError<M>> {
+    state_override_into_reth(state)
+        .map_err(|err| RethMiddlewareError::Conversion(format!("invalid state override: {err}")))
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Executes `tx` at `block` with the `overrides` applied, without creating a transaction.
+    pub async fn call_with_overrides(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<EthersBlockId>,
+        overrides: CallOverrides,
+    ) -> Result<EthersBytes, RethMiddlewareError<M>> {
+        let result = self
+            .reth_api
+            .call(tx.into_reth(), block.into_reth(), overrides.try_into_reth::<M>()?)
+            .await?;
+        Ok(result.into_ethers())
+    }
+
+    /// Estimates the gas `tx` needs at `block` with the `overrides` applied.
+    ///
+    /// Without overrides this is reth's `eth_estimateGas`, which also caps the gas by the balance
+    /// of the sender and answers plain transfers without a search. With overrides, this searches
+    /// for the lowest gas limit the call succeeds with, bounded by the gas of `tx` or the gas cap.
+    pub async fn estimate_gas_with_overrides(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<EthersBlockId>,
+        overrides: CallOverrides,
+    ) -> Result<EthersU256, RethMiddlewareError<M>> {
+        // reth's estimation is called by path, importing `EthApiServer` would make `call` ambiguous
+        if overrides.is_empty() {
+            let gas = reth_rpc_api::EthApiServer::estimate_gas(
+                &self.reth_api,
+                tx.into_reth(),
+                block.into_reth(),
+            )
+            .await?;
+            return Ok(gas.into())
+        }
+
+        let mut request: CallRequest = tx.into_reth();
+        let at = block_or_latest(block);
+        let overrides = overrides.try_into_reth::<M>()?;
+
+        let (res, env) =
+            self.reth_api.transact_call_at(request.clone(), at, overrides.clone()).await?;
+        let gas_used = res.result.gas_used();
+        ensure_success(res.result)?;
+
+        // the call fails with `lowest` and succeeds with `highest`
+        let mut lowest = gas_used.saturating_sub(1);
+        let mut highest = env.tx.gas_limit;
+        while highest - lowest > 1 {
+            let mid = lowest + (highest - lowest) / 2;
+            request.gas = Some(U256::from(mid));
+
+            match self.reth_api.transact_call_at(request.clone(), at, overrides.clone()).await {
+                Ok((res, _)) if res.result.is_success() => highest = mid,
+                Ok(_) | Err(EthApiError::InvalidTransaction(_)) => lowest = mid,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(highest.into())
+    }
+
+    /// Creates the access list of `tx` at `block` with the `overrides` applied, with the gas
+    /// used by `tx` when sent with that access list.
+    ///
+    /// Without overrides this is reth's `eth_createAccessList`. With overrides, the access list
+    /// is collected the same way, by reth's [`AccessListInspector`], and holds every account and
+    /// storage slot the call loads, except for the sender, the recipient and the precompiles.
+    pub async fn create_access_list_with_overrides(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<EthersBlockId>,
+        overrides: CallOverrides,
+    ) -> Result<EthersAccessListWithGasUsed, RethMiddlewareError<M>> {
+        if overrides.is_empty() {
+            let result = reth_rpc_api::EthApiServer::create_access_list(
+                &self.reth_api,
+                tx.into_reth(),
+                block.into_reth(),
+            )
+            .await?;
+            return Ok(result.into_ethers())
+        }
+
+        let mut request: CallRequest = tx.into_reth();
+        let at = block_or_latest(block);
+        let overrides = overrides.try_into_reth::<M>()?;
+
+        let initial = request.access_list.clone().unwrap_or_default();
+        let (result, access_list) = self
+            .reth_api
+            .spawn_with_call_at(request.clone(), at, overrides.clone(), move |mut db, env| {
+                let from = env.tx.caller;
+                let to = match env.tx.transact_to {
+                    TransactTo::Call(to) => to,
+                    TransactTo::Create(_) => {
+                        let nonce = db.basic(from)?.unwrap_or_default().nonce;
+                        get_contract_address(from.into_ethers(), nonce).into_reth()
+                    }
+                };
+                let precompiles = (1..=MAX_PRECOMPILE).map(Address::from_low_u64_be);
+                let mut inspector = AccessListInspector::new(initial, from, to, precompiles);
+
+                let mut evm = EVM::with_env(env);
+                evm.database(db);
+                let ResultAndState { result, .. } = evm.inspect(&mut inspector)?;
+                Ok((result, inspector.into_access_list()))
+            })
+            .await?;
+        ensure_success(result)?;
+
+        request.access_list = Some(access_list.clone());
+        let (res, _) = self.reth_api.transact_call_at(request, at, overrides).await?;
+        let gas_used = res.result.gas_used();
+        ensure_success(res.result)?;
+
+        Ok(AccessListWithGasUsed { access_list, gas_used: U256::from(gas_used) }.into_ethers())
+    }
+}
+
+fn block_or_latest(block: Option<EthersBlockId>) -> BlockId {
+    let block: Option<BlockId> = block.into_reth();
+    block.unwrap_or(BlockId::Number(BlockNumberOrTag::Latest))
+}
+
+/// Returns the output of a successful execution.
+pub(crate) fn ensure_success<M: Middleware>(
+    result: ExecutionResult,
+) -> Result<EthersBytes, RethMiddlewareError<M>> {
+    let ExecutionOutcome { output, failure, .. } = result.into();
+    match failure {
+        None => Ok(output),
+        Some(ExecutionFailure::Revert(_)) => {
+            Err(RethMiddlewareError::ExecutionReverted { data: Some(output) })
+        }
+        Some(ExecutionFailure::Halt(Halt::OutOfGas(_))) => Err(RethMiddlewareError::OutOfGas),
+        Some(failure) => {
+            Err(ErrorObjectOwned::owned(VM_EXECUTION_ERROR_CODE, failure.to_string(), None::<()>)
+                .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::OfflineClient;
+    use ethers::{providers::Provider, types::Address as EthersAddress};
+
+    #[test]
+    fn call_overrides_into_reth() {
+        let address = EthersAddress::repeat_byte(1);
+        let mut state = spoof::state();
+        state.account(address).balance(1_000u64.into());
+        let block = BlockOverrides { base_fee: Some(9u64.into()), ..Default::default() };
+
+        let overrides = CallOverrides::from(state)
+            .block(block)
+            .try_into_reth::<Provider<OfflineClient>>()
+            .unwrap();
+        let address: Address = address.into_reth();
+        assert_eq!(overrides.state.unwrap()[&address].balance, Some(U256::from(1_000)));
+        assert_eq!(overrides.block.unwrap().base_fee, Some(U256::from(9)));
+    }
+
+    #[test]
+    fn empty_overrides() {
+        assert!(CallOverrides::new().is_empty());
+        assert!(!CallOverrides::from(spoof::state()).is_empty());
+        assert!(!CallOverrides::from(BlockOverrides::default()).is_empty());
+    }
+}
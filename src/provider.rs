@@ -0,0 +1,114 @@
+//! A [`RethMiddleware`] that serves requests from the database alone.
+
+use crate::{
+    builder::RethMiddlewareBuilder, chain::ChainSource, init::InitError, RethMiddleware,
+    METHOD_NOT_FOUND_CODE,
+};
+use std::{fmt::Debug, path::Path};
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+use tokio::runtime::Handle;
+
+/// A [`RethMiddleware`] without a network provider.
+///
+/// Methods that cannot be served from the database fail with
+/// [`OfflineClientError::Unsupported`], which
+/// [`RethMiddlewareError::is_unsupported`](crate::RethMiddlewareError::is_unsupported) detects.
+pub type RethProvider = RethMiddleware<Provider<OfflineClient>>;
+
+/// A JSON-RPC client that is not connected to any node and rejects every request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfflineClient;
+
+/// The error returned by every request to an [`OfflineClient`].
+#[derive(Error, Debug)]
+pub enum OfflineClientError {
+    /// The method can only be served by a node.
+    ///
+    /// It is reported as a JSON-RPC "method not found" response, so that it can be told apart
+    /// from other errors after ethers boxed it into a [`ProviderError`].
+    #[error("{method} is not supported without a network provider")]
+    Unsupported { method: String, response: JsonRpcError },
+}
+
+impl OfflineClientError {
+    fn unsupported(method: &str) -> Self {
+        let response = JsonRpcError {
+            code: METHOD_NOT_FOUND_CODE,
+            message: format!("{method} is not supported without a network provider"),
+            data: None,
+        };
+        OfflineClientError::Unsupported { method: method.to_string(), response }
+    }
+}
+
+impl RpcError for OfflineClientError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            OfflineClientError::Unsupported { response, .. } => Some(response),
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        None
+    }
+}
+
+impl From<OfflineClientError> for ProviderError {
+    fn from(src: OfflineClientError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for OfflineClient {
+    type Error = OfflineClientError;
+
+    async fn request<T, R>(&self, method: &str, _params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        Err(OfflineClientError::unsupported(method))
+    }
+}
+
+impl RethProvider {
+    /// Opens the database at `db_path` with the default limits, without a network provider.
+    pub fn standalone<P: AsRef<Path>, C: Into<ChainSource>>(
+        db_path: P,
+        handle: Handle,
+        chain: C,
+    ) -> Result<Self, InitError> {
+        RethMiddlewareBuilder::new(db_path, handle, chain).build_standalone()
+    }
+}
+
+impl RethMiddlewareBuilder {
+    /// Opens the database and spawns the reth components, without a network provider.
+    pub fn build_standalone(self) -> Result<RethProvider, InitError> {
+        self.build(Provider::new(OfflineClient))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RethMiddlewareError;
+    use ethers::providers::{Middleware, MiddlewareError};
+
+    #[tokio::test]
+    async fn rejects_requests() {
+        let provider = Provider::new(OfflineClient);
+        let err = provider.get_net_version().await.unwrap_err();
+
+        assert_eq!(err.to_string(), "net_version is not supported without a network provider");
+
+        let err = RethMiddlewareError::<Provider<OfflineClient>>::from_err(err);
+        assert!(err.is_unsupported());
+        assert!(!RethMiddlewareError::<Provider<OfflineClient>>::BlockNotFound.is_unsupported());
+    }
+}
@@ -0,0 +1,559 @@
+//! Shadow comparison of local results against a reference middleware.
+//!
+//! The conversions between reth and ethers types are lossy in places. A [`ShadowMiddleware`]
+//! wraps a middleware serving requests locally and sends a sampled fraction of the requests to a
+//! reference middleware as well, e.g. the node the local database is synced from. The serialized
+//! results are diffed in the background to find where the two diverge.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Middleware, MiddlewareError},
+    types::{
+        transaction::eip2718::TypedTransaction, Block, BlockId, BlockNumber, BlockTrace, Bytes,
+        Filter, Log, NameOrAddress, Trace, TraceFilter, TraceType, Transaction, TransactionReceipt,
+        TxHash, H256, U256,
+    },
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+/// A value that differs between the local and the inner result.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// The path of the value in the serialized result, e.g. `$.transactions[0].to`.
+    pub path: String,
+    /// The local value, `None` if absent.
+    pub local: Option<Value>,
+    /// The value returned by the inner middleware, `None` if absent.
+    pub inner: Option<Value>,
+}
+
+/// The differences between the local and the inner result of a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The name of the [`Middleware`](ethers::providers::Middleware) method.
+    pub method: &'static str,
+    pub differences: Vec<Difference>,
+}
+
+/// A callback invoked with every divergence found in shadow mode.
+#[derive(Clone)]
+pub struct DivergenceReporter(Arc<dyn Fn(&Divergence) + Send + Sync>);
+
+impl DivergenceReporter {
+    pub fn new<F>(reporter: F) -> Self
+    where
+        F: Fn(&Divergence) + Send + Sync + 'static,
+    {
+        Self(Arc::new(reporter))
+    }
+}
+
+impl fmt::Debug for DivergenceReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DivergenceReporter").finish_non_exhaustive()
+    }
+}
+
+/// Which requests are shadowed and where divergences are reported, see [`ShadowMiddleware`].
+#[derive(Debug, Clone, Default)]
+pub struct ShadowConfig {
+    sample_rate: f64,
+    reporter: Option<DivergenceReporter>,
+}
+
+impl ShadowConfig {
+    /// Shadows the fraction `sample_rate` of the requests, clamped to `[0, 1]`.
+    ///
+    /// Divergences are logged as warnings unless a reporter is set.
+    pub fn new(sample_rate: f64) -> Self {
+        let sample_rate = if sample_rate.is_nan() { 0.0 } else { sample_rate.clamp(0.0, 1.0) };
+        Self { sample_rate, reporter: None }
+    }
+
+    /// Reports divergences to `reporter` instead of the log.
+    pub fn reporter<F>(mut self, reporter: F) -> Self
+    where
+        F: Fn(&Divergence) + Send + Sync + 'static,
+    {
+        self.reporter = Some(DivergenceReporter::new(reporter));
+        self
+    }
+}
+
+/// The sampling and reporting of a [`ShadowMiddleware`].
+#[derive(Debug, Default)]
+pub(crate) struct Shadow {
+    config: ShadowConfig,
+    calls: AtomicU64,
+}
+
+impl Shadow {
+    pub(crate) fn new(config: ShadowConfig) -> Self {
+        Self { config, calls: AtomicU64::new(0) }
+    }
+
+    /// Returns true if the current request is shadowed.
+    ///
+    /// Requests are sampled evenly, e.g. every tenth request at a rate of `0.1`.
+    pub(crate) fn sampled(&self) -> bool {
+        let rate = self.config.sample_rate;
+        if rate == 0.0 {
+            return false
+        }
+
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) as f64;
+        ((calls + 1.0) * rate).floor() > (calls * rate).floor()
+    }
+
+    /// Diffs the serialized results and reports a divergence if they differ.
+    pub(crate) fn compare(&self, method: &'static str, local: &Value, inner: &Value) {
+        let differences = diff(local, inner);
+        if differences.is_empty() {
+            return
+        }
+
+        let divergence = Divergence { method, differences };
+        match &self.config.reporter {
+            Some(reporter) => (reporter.0)(&divergence),
+            None => {
+                for difference in &divergence.differences {
+                    tracing::warn!(
+                        target: "ethers_reth::shadow",
+                        method,
+                        path = %difference.path,
+                        local = ?difference.local,
+                        inner = ?difference.inner,
+                        "local result diverges from inner middleware"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// A middleware that compares a sample of the answers of `inner` against `reference`.
+///
+/// Requests are always answered by `inner`. For a sampled request the same request is sent to
+/// `reference` on a spawned task, so the comparison adds no latency. Only the requests a
+/// [`RethMiddleware`](crate::RethMiddleware) serves from the database are shadowed, others are
+/// passed to `inner` unchanged.
+#[derive(Debug)]
+pub struct ShadowMiddleware<M, R> {
+    inner: M,
+    reference: Arc<R>,
+    shadow: Arc<Shadow>,
+}
+
+impl<M: Clone, R> Clone for ShadowMiddleware<M, R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            reference: self.reference.clone(),
+            shadow: self.shadow.clone(),
+        }
+    }
+}
+
+impl<M, R> ShadowMiddleware<M, R>
+where
+    M: Middleware,
+    R: Middleware + 'static,
+{
+    pub fn new(inner: M, reference: R, config: ShadowConfig) -> Self {
+        Self { inner, reference: Arc::new(reference), shadow: Arc::new(Shadow::new(config)) }
+    }
+
+    /// Sends a sampled request to the reference middleware in the background and reports where
+    /// its answer diverges from `local`.
+    fn shadow<T, F, Fut>(&self, method: &'static str, local: &Result<T, M::Error>, request: F)
+    where
+        T: Serialize,
+        F: FnOnce(Arc<R>) -> Fut,
+        Fut: Future<Output = Result<T, R::Error>> + Send + 'static,
+    {
+        if !self.shadow.sampled() {
+            return
+        }
+
+        let local = to_value(local);
+        let reference = request(self.reference.clone());
+        let shadow = self.shadow.clone();
+        tokio::spawn(async move {
+            shadow.compare(method, &local, &to_value(&reference.await));
+        });
+    }
+}
+
+/// An error of the middleware wrapped by a [`ShadowMiddleware`].
+#[derive(Error, Debug)]
+#[error(transparent)]
+pub struct ShadowMiddlewareError<M: Middleware>(M::Error);
+
+impl<M: Middleware> MiddlewareError for ShadowMiddlewareError<M> {
+    type Inner = M::Error;
+
+    fn from_err(e: Self::Inner) -> Self {
+        ShadowMiddlewareError(e)
+    }
+
+    fn as_inner(&self) -> Option<&Self::Inner> {
+        Some(&self.0)
+    }
+}
+
+#[async_trait]
+impl<M, R> Middleware for ShadowMiddleware<M, R>
+where
+    M: Middleware,
+    R: Middleware + 'static,
+{
+    type Error = ShadowMiddlewareError<M>;
+    type Provider = M::Provider;
+    type Inner = M;
+
+    fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    async fn call(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, Self::Error> {
+        let local = self.inner.call(tx, block).await;
+        let tx = tx.clone();
+        self.shadow(
+            "call",
+            &local,
+            move |reference| async move { reference.call(&tx, block).await },
+        );
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn estimate_gas(
+        &self,
+        tx: &TypedTransaction,
+        block: Option<BlockId>,
+    ) -> Result<U256, Self::Error> {
+        let local = self.inner.estimate_gas(tx, block).await;
+        let tx = tx.clone();
+        self.shadow("estimate_gas", &local, move |reference| async move {
+            reference.estimate_gas(&tx, block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_storage_at<T: Into<NameOrAddress> + Send + Sync>(
+        &self,
+        from: T,
+        location: H256,
+        block: Option<BlockId>,
+    ) -> Result<H256, Self::Error> {
+        let from: NameOrAddress = from.into();
+        let local = self.inner.get_storage_at(from.clone(), location, block).await;
+        self.shadow("get_storage_at", &local, move |reference| async move {
+            reference.get_storage_at(from, location, block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_code<T: Into<NameOrAddress> + Send + Sync>(
+        &self,
+        at: T,
+        block: Option<BlockId>,
+    ) -> Result<Bytes, Self::Error> {
+        let at: NameOrAddress = at.into();
+        let local = self.inner.get_code(at.clone(), block).await;
+        self.shadow("get_code", &local, move |reference| async move {
+            reference.get_code(at, block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_balance<T: Into<NameOrAddress> + Send + Sync>(
+        &self,
+        from: T,
+        block: Option<BlockId>,
+    ) -> Result<U256, Self::Error> {
+        let from: NameOrAddress = from.into();
+        let local = self.inner.get_balance(from.clone(), block).await;
+        self.shadow("get_balance", &local, move |reference| async move {
+            reference.get_balance(from, block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_transaction_count<T: Into<NameOrAddress> + Send + Sync>(
+        &self,
+        from: T,
+        block: Option<BlockId>,
+    ) -> Result<U256, Self::Error> {
+        let from: NameOrAddress = from.into();
+        let local = self.inner.get_transaction_count(from.clone(), block).await;
+        self.shadow("get_transaction_count", &local, move |reference| async move {
+            reference.get_transaction_count(from, block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_block_receipts<T: Into<BlockNumber> + Send + Sync>(
+        &self,
+        block: T,
+    ) -> Result<Vec<TransactionReceipt>, Self::Error> {
+        let block: BlockNumber = block.into();
+        let local = self.inner.get_block_receipts(block).await;
+        self.shadow("get_block_receipts", &local, move |reference| async move {
+            reference.get_block_receipts(block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_transaction<T: Send + Sync + Into<TxHash>>(
+        &self,
+        transaction_hash: T,
+    ) -> Result<Option<Transaction>, Self::Error> {
+        let hash: TxHash = transaction_hash.into();
+        let local = self.inner.get_transaction(hash).await;
+        self.shadow("get_transaction", &local, move |reference| async move {
+            reference.get_transaction(hash).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_transaction_receipt<T: Send + Sync + Into<TxHash>>(
+        &self,
+        transaction_hash: T,
+    ) -> Result<Option<TransactionReceipt>, Self::Error> {
+        let hash: TxHash = transaction_hash.into();
+        let local = self.inner.get_transaction_receipt(hash).await;
+        self.shadow("get_transaction_receipt", &local, move |reference| async move {
+            reference.get_transaction_receipt(hash).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_block<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<Block<TxHash>>, Self::Error> {
+        let block: BlockId = block_hash_or_number.into();
+        let local = self.inner.get_block(block).await;
+        self.shadow("get_block", &local, move |reference| async move {
+            reference.get_block(block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_block_with_txs<T: Into<BlockId> + Send + Sync>(
+        &self,
+        block_hash_or_number: T,
+    ) -> Result<Option<Block<Transaction>>, Self::Error> {
+        let block: BlockId = block_hash_or_number.into();
+        let local = self.inner.get_block_with_txs(block).await;
+        self.shadow("get_block_with_txs", &local, move |reference| async move {
+            reference.get_block_with_txs(block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>, Self::Error> {
+        let local = self.inner.get_logs(filter).await;
+        let filter = filter.clone();
+        self.shadow("get_logs", &local, move |reference| async move {
+            reference.get_logs(&filter).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn trace_replay_transaction(
+        &self,
+        hash: H256,
+        trace_type: Vec<TraceType>,
+    ) -> Result<BlockTrace, Self::Error> {
+        let local = self.inner.trace_replay_transaction(hash, trace_type.clone()).await;
+        self.shadow("trace_replay_transaction", &local, move |reference| async move {
+            reference.trace_replay_transaction(hash, trace_type).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn trace_replay_block_transactions(
+        &self,
+        block: BlockNumber,
+        trace_type: Vec<TraceType>,
+    ) -> Result<Vec<BlockTrace>, Self::Error> {
+        let local = self.inner.trace_replay_block_transactions(block, trace_type.clone()).await;
+        self.shadow("trace_replay_block_transactions", &local, move |reference| async move {
+            reference.trace_replay_block_transactions(block, trace_type).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn trace_block(&self, block: BlockNumber) -> Result<Vec<Trace>, Self::Error> {
+        let local = self.inner.trace_block(block).await;
+        self.shadow("trace_block", &local, move |reference| async move {
+            reference.trace_block(block).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn trace_filter(&self, filter: TraceFilter) -> Result<Vec<Trace>, Self::Error> {
+        let local = self.inner.trace_filter(filter.clone()).await;
+        self.shadow("trace_filter", &local, move |reference| async move {
+            reference.trace_filter(filter).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+
+    async fn trace_transaction(&self, hash: H256) -> Result<Vec<Trace>, Self::Error> {
+        let local = self.inner.trace_transaction(hash).await;
+        self.shadow("trace_transaction", &local, move |reference| async move {
+            reference.trace_transaction(hash).await
+        });
+        local.map_err(MiddlewareError::from_err)
+    }
+}
+
+/// Serializes a result, errors are compared by their message.
+fn to_value<T: Serialize, E: Display>(result: &Result<T, E>) -> Value {
+    match result {
+        Ok(value) => serde_json::to_value(value)
+            .unwrap_or_else(|err| json!({ "serializationError": err.to_string() })),
+        Err(err) => json!({ "error": err.to_string() }),
+    }
+}
+
+/// Returns the values that differ between two JSON values.
+///
+/// A missing field and a `null` field are considered equal.
+pub fn diff(local: &Value, inner: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_at("$".to_string(), Some(local), Some(inner), &mut differences);
+    differences
+}
+
+fn diff_at(
+    path: String,
+    local: Option<&Value>,
+    inner: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    let local = local.filter(|value| !value.is_null());
+    let inner = inner.filter(|value| !value.is_null());
+
+    match (local, inner) {
+        (Some(Value::Object(local)), Some(Value::Object(inner))) => {
+            let keys: BTreeSet<&String> = local.keys().chain(inner.keys()).collect();
+            for key in keys {
+                diff_at(format!("{path}.{key}"), local.get(key), inner.get(key), differences);
+            }
+        }
+        (Some(Value::Array(local)), Some(Value::Array(inner))) => {
+            for index in 0..local.len().max(inner.len()) {
+                diff_at(
+                    format!("{path}[{index}]"),
+                    local.get(index),
+                    inner.get(index),
+                    differences,
+                );
+            }
+        }
+        (local, inner) if local == inner => {}
+        (local, inner) => {
+            differences.push(Difference { path, local: local.cloned(), inner: inner.cloned() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_nested_values() {
+        let local = json!({
+            "hash": "0x01",
+            "transactions": [{ "to": "0x02", "other": null }],
+        });
+        let inner = json!({
+            "hash": "0x01",
+            "transactions": [{ "to": "0x03" }, { "to": "0x04" }],
+            "blobGasUsed": "0x0",
+        });
+
+        assert_eq!(
+            diff(&local, &inner),
+            vec![
+                Difference {
+                    path: "$.blobGasUsed".to_string(),
+                    local: None,
+                    inner: Some(json!("0x0")),
+                },
+                Difference {
+                    path: "$.transactions[0].to".to_string(),
+                    local: Some(json!("0x02")),
+                    inner: Some(json!("0x03")),
+                },
+                Difference {
+                    path: "$.transactions[1]".to_string(),
+                    local: None,
+                    inner: Some(json!({ "to": "0x04" })),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_equal_values() {
+        let value = json!({ "number": "0x3", "uncles": [] });
+        assert!(diff(&value, &value).is_empty());
+    }
+
+    #[test]
+    fn sampling() {
+        let sampled = |rate: f64| {
+            let shadow = Shadow::new(ShadowConfig::new(rate));
+            (0..100).filter(|_| shadow.sampled()).count()
+        };
+
+        assert_eq!(sampled(0.0), 0);
+        assert_eq!(sampled(0.1), 10);
+        assert_eq!(sampled(0.25), 25);
+        assert_eq!(sampled(1.0), 100);
+        assert_eq!(sampled(2.0), 100);
+    }
+
+    #[test]
+    fn reports_divergences() {
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let divergences = reported.clone();
+        let shadow = Shadow::new(
+            ShadowConfig::new(1.0)
+                .reporter(move |divergence| divergences.lock().unwrap().push(divergence.clone())),
+        );
+
+        let local = to_value::<_, String>(&Ok(3u64));
+        shadow.compare("get_block_number", &local, &to_value::<_, String>(&Ok(3u64)));
+        shadow.compare(
+            "get_block_number",
+            &local,
+            &to_value::<u64, _>(&Err("rate limited".to_string())),
+        );
+
+        let reported = reported.lock().unwrap();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].differences[0].inner, Some(json!({ "error": "rate limited" })));
+    }
+}
@@ -0,0 +1,171 @@
+//! Shutdown of the background tasks spawned for a [`RethMiddleware`].
+//!
+//! [`RethMiddleware`]: crate::RethMiddleware
+
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use reth_db::mdbx::{Env, WriteMap};
+use reth_tasks::TaskManager;
+use thiserror::Error;
+use tokio::{
+    runtime::Handle,
+    sync::{oneshot, watch},
+    time::{sleep, Instant},
+};
+
+/// How often [`ShutdownHandle::shutdown`] checks whether the database has been closed.
+const DB_CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long [`ShutdownHandle::shutdown`] waits for the database to be closed.
+pub const DEFAULT_DB_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The database was not closed by a shutdown.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownError {
+    /// Something other than the background tasks still holds the database, e.g. a clone of the
+    /// middleware, a [`ForkSession`](crate::fork::ForkSession) or a
+    /// [`RethDatabase`](crate::database::RethDatabase).
+    #[error(
+        "database is still held by {references} references after {timeout:?}, drop every \
+         middleware, provider, fork session and revm database opened on it"
+    )]
+    DatabaseInUse { references: usize, timeout: Duration },
+}
+
+/// Owns the [`TaskManager`] of a middleware and its clones.
+///
+/// The background tasks are shut down by [`ShutdownHandle::shutdown`] or once the last clone of
+/// the handle is dropped.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Dropping the sender shuts down the task manager, so no explicit `Drop` is needed.
+    signal: Mutex<Option<oneshot::Sender<()>>>,
+    stopped: watch::Receiver<bool>,
+    db: Weak<Env<WriteMap>>,
+}
+
+impl ShutdownHandle {
+    /// Spawns a task that drops `task_manager`, and with it every task spawned on its executor,
+    /// once shutdown is signaled or a critical task panics.
+    pub(crate) fn spawn(
+        handle: &Handle,
+        task_manager: TaskManager,
+        db: &Arc<Env<WriteMap>>,
+    ) -> Self {
+        let (signal, on_signal) = oneshot::channel();
+        let (stopped_tx, stopped) = watch::channel(false);
+
+        handle.spawn(async move {
+            tokio::select! {
+                _ = task_manager => {}
+                _ = on_signal => {}
+            }
+            let _ = stopped_tx.send(true);
+        });
+
+        Self {
+            inner: Arc::new(Inner {
+                signal: Mutex::new(Some(signal)),
+                stopped,
+                db: Arc::downgrade(db),
+            }),
+        }
+    }
+
+    /// Returns true once the background tasks have been shut down.
+    pub fn is_stopped(&self) -> bool {
+        *self.inner.stopped.borrow()
+    }
+
+    /// Shuts down the background tasks and waits for them to stop.
+    ///
+    /// If this is the last handle, this also waits up to [`DEFAULT_DB_CLOSE_TIMEOUT`] until the
+    /// database is closed, which requires that everything reading from it has been dropped.
+    pub async fn shutdown(self) -> Result<(), ShutdownError> {
+        self.shutdown_with_timeout(DEFAULT_DB_CLOSE_TIMEOUT).await
+    }
+
+    /// Shuts down the background tasks like [`ShutdownHandle::shutdown`], waiting up to `timeout`
+    /// for the database to be closed.
+    pub async fn shutdown_with_timeout(self, timeout: Duration) -> Result<(), ShutdownError> {
+        if let Some(signal) = self.inner.signal.lock().unwrap().take() {
+            let _ = signal.send(());
+        }
+
+        let mut stopped = self.inner.stopped.clone();
+        while !*stopped.borrow_and_update() {
+            if stopped.changed().await.is_err() {
+                break
+            }
+        }
+
+        let db = self.inner.db.clone();
+        let is_last = Arc::strong_count(&self.inner) == 1;
+        drop(self);
+
+        if is_last {
+            // stopped tasks release the database as their futures are dropped
+            let deadline = Instant::now() + timeout;
+            while db.strong_count() > 0 {
+                if Instant::now() >= deadline {
+                    return Err(ShutdownError::DatabaseInUse {
+                        references: db.strong_count(),
+                        timeout,
+                    })
+                }
+                sleep(DB_CLOSE_POLL_INTERVAL).await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::init_db;
+    use std::path::Path;
+
+    fn spawn_with_reader() -> (ShutdownHandle, Arc<Env<WriteMap>>) {
+        let db_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join("db");
+        let db = Arc::new(init_db(db_path).unwrap());
+
+        let handle = Handle::current();
+        let task_manager = TaskManager::new(handle.clone());
+        let reader = db.clone();
+        task_manager.executor().spawn(Box::pin(async move {
+            let _reader = reader;
+            futures::future::pending::<()>().await
+        }));
+
+        (ShutdownHandle::spawn(&handle, task_manager, &db), db)
+    }
+
+    // a single test, as the same environment cannot be opened twice at once in a process
+    #[tokio::test]
+    async fn closes_database() {
+        let (shutdown_handle, db) = spawn_with_reader();
+        let closed = Arc::downgrade(&db);
+        drop(db);
+
+        shutdown_handle.shutdown().await.unwrap();
+        assert!(closed.upgrade().is_none());
+
+        let (shutdown_handle, db) = spawn_with_reader();
+        let timeout = Duration::from_millis(50);
+        assert_eq!(
+            shutdown_handle.shutdown_with_timeout(timeout).await,
+            Err(ShutdownError::DatabaseInUse { references: 1, timeout })
+        );
+        drop(db);
+    }
+}
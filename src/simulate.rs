@@ -0,0 +1,281 @@
+//! Simulation of block sequences, in the style of `eth_simulateV1`.
+//!
+//! Every [`SimulatedBlockSpec`] describes a block built on top of the previous one: its header
+//! fields and accounts are overridden with [`CallOverrides`], then its calls are executed in order.
+//! The state after each block is the state of the next one. Nothing is written to the database.
+
+use crate::{
+    bundle::{ethers_log, transact_and_commit, ExecutionOutcome, BLOCK_TIME},
+    call_many::{call_response, call_tx_env, CallResponse},
+    overrides::CallOverrides,
+    type_conversions::{ToEthers, ToReth},
+    RethMiddleware, RethMiddlewareError,
+};
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address as EthersAddress, BlockId as EthersBlockId,
+        Bytes as EthersBytes, Log as EthersLog, U256 as EthersU256,
+    },
+};
+use reth_primitives::{Header, U256};
+use reth_provider::BlockReaderIdExt;
+use reth_revm::{
+    database::{State, SubState},
+    primitives::{BlockEnv, Env, TxEnv},
+};
+use reth_rpc::eth::{
+    error::EthApiError,
+    revm_utils::{apply_block_overrides, apply_state_overrides},
+    EthTransactions,
+};
+use reth_rpc_types::state::StateOverride;
+
+/// A simulated block, ready to be executed.
+struct SimulationStep {
+    block_env: BlockEnv,
+    /// Whether the base fee was overridden instead of following from the previous block.
+    base_fee_overridden: bool,
+    state_overrides: Option<StateOverride>,
+    txs: Vec<TxEnv>,
+}
+
+/// A block to simulate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulatedBlockSpec {
+    /// The header overrides of the block and the state overrides applied before its calls.
+    pub overrides: CallOverrides,
+    pub calls: Vec<TypedTransaction>,
+}
+
+impl SimulatedBlockSpec {
+    pub fn new(calls: Vec<TypedTransaction>) -> Self {
+        Self { overrides: CallOverrides::default(), calls }
+    }
+
+    /// Sets the header and state overrides.
+    pub fn overrides(mut self, overrides: impl Into<CallOverrides>) -> Self {
+        self.overrides = overrides.into();
+        self
+    }
+}
+
+/// The outcome of a simulated call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedCall {
+    /// The return data, or the revert data if the call reverted.
+    pub return_data: EthersBytes,
+    pub gas_used: u64,
+    pub logs: Vec<EthersLog>,
+    /// Whether the call succeeded.
+    pub status: bool,
+    /// Why the call failed, with the decoded revert reason if it reverted.
+    pub error: Option<String>,
+}
+
+/// A synthetic block produced by a simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedBlock {
+    pub number: u64,
+    pub timestamp: u64,
+    pub base_fee: EthersU256,
+    pub coinbase: EthersAddress,
+    pub gas_limit: u64,
+    /// The gas used by all calls of the block.
+    pub gas_used: u64,
+    pub calls: Vec<SimulatedCall>,
+}
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Simulates `blocks` in order on top of the state of `block`.
+    ///
+    /// Each simulated block follows the previous one, or `block` for the first: it is numbered
+    /// one after it, its timestamp is 12 seconds later and its base fee follows from the gas
+    /// used by the previous block, unless overridden. Overridden numbers and timestamps must
+    /// still increase from block to block. The other header fields are inherited. Calls are
+    /// executed without a signature and pay no fees unless they set a gas price. Failed calls
+    /// do not stop the simulation, their outcome is reported in the results.
+    pub async fn simulate_blocks<B: Into<EthersBlockId>>(
+        &self,
+        blocks: Vec<SimulatedBlockSpec>,
+        block: B,
+    ) -> Result<Vec<SimulatedBlock>, RethMiddlewareError<M>> {
+        let block: EthersBlockId = block.into();
+        let (mut cfg, mut block_env, at) = self.reth_api.evm_env_at(block.into_reth()).await?;
+        cfg.disable_base_fee = true;
+        cfg.disable_eip3607 = true;
+        let parent = self
+            .reth_client()
+            .header_by_id(at)
+            .map_err(EthApiError::from)?
+            .ok_or(RethMiddlewareError::BlockNotFound)?;
+
+        let mut steps = Vec::with_capacity(blocks.len());
+        for spec in blocks {
+            let (parent_number, parent_timestamp) = (block_env.number, block_env.timestamp);
+            block_env.number += U256::from(1);
+            block_env.timestamp += U256::from(BLOCK_TIME);
+
+            let overrides = spec.overrides.try_into_reth::<M>()?;
+            let base_fee_overridden =
+                overrides.block.as_ref().map_or(false, |block| block.base_fee.is_some());
+            if let Some(block_overrides) = overrides.block {
+                apply_block_overrides(*block_overrides, &mut block_env);
+            }
+            if block_env.number <= parent_number || block_env.timestamp <= parent_timestamp {
+                return Err(RethMiddlewareError::InvalidParams(format!(
+                    "simulated block {} at {} does not follow block {parent_number} at \
+                     {parent_timestamp}",
+                    block_env.number, block_env.timestamp
+                )))
+            }
+
+            let txs = spec.calls.iter().map(|tx| call_tx_env(tx, &block_env)).collect();
+            steps.push(SimulationStep {
+                block_env: block_env.clone(),
+                base_fee_overridden,
+                state_overrides: overrides.state,
+                txs,
+            });
+        }
+
+        let executed =
+            self.reth_api
+                .spawn_with_state_at_block(at, move |state| {
+                    let mut db = SubState::new(State::new(state));
+                    let mut parent = parent;
+                    let mut executed = Vec::with_capacity(steps.len());
+                    for step in steps {
+                        let SimulationStep {
+                            mut block_env,
+                            base_fee_overridden,
+                            state_overrides,
+                            txs,
+                        } = step;
+                        if !base_fee_overridden {
+                            if let Some(base_fee) = parent.next_block_base_fee() {
+                                block_env.basefee = U256::from(base_fee);
+                            }
+                        }
+                        if let Some(state_overrides) = state_overrides {
+                            apply_state_overrides(state_overrides, &mut db)?;
+                        }
+
+                        let mut results = Vec::with_capacity(txs.len());
+                        for tx in txs {
+                            let env = Env { cfg: cfg.clone(), block: block_env.clone(), tx };
+                            results.push(transact_and_commit(&mut db, env)?.outcome);
+                        }
+                        let gas_used = results.iter().map(|outcome| outcome.gas_used).sum();
+                        parent = simulated_header(&block_env, gas_used);
+                        executed.push((block_env, results));
+                    }
+                    Ok(executed)
+                })
+                .await?;
+
+        Ok(executed
+            .into_iter()
+            .map(|(block_env, results)| simulated_block(block_env, results))
+            .collect())
+    }
+}
+
+/// Returns the header fields of a simulated block that the next block's base fee follows from.
+fn simulated_header(block_env: &BlockEnv, gas_used: u64) -> Header {
+    Header {
+        number: u64::try_from(block_env.number).unwrap_or_default(),
+        timestamp: u64::try_from(block_env.timestamp).unwrap_or_default(),
+        gas_limit: u64::try_from(block_env.gas_limit).unwrap_or(u64::MAX),
+        gas_used,
+        base_fee_per_gas: u64::try_from(block_env.basefee).ok(),
+        ..Default::default()
+    }
+}
+
+fn simulated_block(block_env: BlockEnv, results: Vec<ExecutionOutcome>) -> SimulatedBlock {
+    let number = u64::try_from(block_env.number).unwrap_or_default();
+    let mut log_index = 0;
+    let calls: Vec<SimulatedCall> = results
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut outcome)| {
+            let gas_used = outcome.gas_used;
+            let logs = std::mem::take(&mut outcome.logs)
+                .into_iter()
+                .map(|log| {
+                    let mut log = ethers_log(log, None, index, log_index);
+                    log.block_number = Some(number.into());
+                    log_index += 1;
+                    log
+                })
+                .collect();
+
+            let CallResponse { value, error } = call_response(outcome);
+            SimulatedCall {
+                return_data: value.unwrap_or_default(),
+                gas_used,
+                logs,
+                status: error.is_none(),
+                error,
+            }
+        })
+        .collect();
+
+    SimulatedBlock {
+        number,
+        timestamp: u64::try_from(block_env.timestamp).unwrap_or_default(),
+        base_fee: block_env.basefee.into_ethers(),
+        coinbase: block_env.coinbase.into_ethers(),
+        gas_limit: u64::try_from(block_env.gas_limit).unwrap_or(u64::MAX),
+        gas_used: calls.iter().map(|call| call.gas_used).sum(),
+        calls,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::Address;
+    use reth_revm::primitives::{Eval, ExecutionResult, Log, Output};
+
+    #[test]
+    fn simulated_block_results() {
+        let block_env = BlockEnv {
+            number: U256::from(4),
+            timestamp: U256::from(1_012),
+            basefee: U256::from(7),
+            gas_limit: U256::from(30_000_000),
+            ..Default::default()
+        };
+        let log =
+            Log { address: Address::repeat_byte(1), topics: Vec::new(), data: Default::default() };
+        let results = vec![
+            ExecutionResult::Success {
+                reason: Eval::Stop,
+                gas_used: 30_000,
+                gas_refunded: 0,
+                logs: vec![log.clone(), log],
+                output: Output::Call(Default::default()),
+            },
+            ExecutionResult::Revert { gas_used: 21_000, output: vec![0xde].into() },
+        ];
+        let results = results.into_iter().map(ExecutionOutcome::from).collect();
+
+        let block = simulated_block(block_env, results);
+        assert_eq!(block.number, 4);
+        assert_eq!(block.timestamp, 1_012);
+        assert_eq!(block.base_fee, 7.into());
+        assert_eq!(block.gas_used, 51_000);
+        assert!(block.calls[0].status);
+        assert_eq!(block.calls[0].logs[1].log_index, Some(1.into()));
+        assert_eq!(block.calls[0].logs[1].block_number, Some(4.into()));
+        assert!(!block.calls[1].status);
+        assert_eq!(block.calls[1].return_data, EthersBytes::from(vec![0xde]));
+        assert_eq!(block.calls[1].error.as_deref(), Some("execution reverted: 0xde"));
+    }
+}
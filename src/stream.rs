@@ -0,0 +1,225 @@
+//! Streams over data read from the local database.
+
+use crate::{
+    follower::HeadUpdate,
+    type_conversions::{ToEthers, ToReth},
+    RethMiddleware, RethMiddlewareError,
+};
+use std::pin::Pin;
+
+use ethers::{
+    providers::Middleware,
+    types::{
+        Block as EthersBlock, Filter as EthersFilter, FilterBlockOption as EthersFilterBlockOption,
+        Log as EthersLog, H256 as EthersH256,
+    },
+};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+
+use reth_rpc_api::{EthApiServer, EthFilterApiServer};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+/// A stream of logs read from the local database.
+pub type LogStream<'a, M> =
+    Pin<Box<dyn Stream<Item = Result<EthersLog, RethMiddlewareError<M>>> + Send + 'a>>;
+
+/// A stream of the hashes of newly canonical blocks.
+pub type BlockHashStream = Pin<Box<dyn Stream<Item = EthersH256> + Send>>;
+
+/// A stream of newly canonical blocks, read from the local database.
+pub type BlockStream<'a, M> = Pin<
+    Box<dyn Stream<Item = Result<EthersBlock<EthersH256>, RethMiddlewareError<M>>> + Send + 'a>,
+>;
+
+impl<M> RethMiddleware<M>
+where
+    M: Middleware,
+{
+    /// Returns a stream of the logs matching `filter`, loaded from the local database in pages
+    /// of `page_size` blocks.
+    ///
+    /// Only a single page is held in memory at any time. A page that matches more than
+    /// `max_logs_per_response` logs is halved until it fits, and the following pages keep the
+    /// smaller size. A single block with too many logs fails the stream.
+    ///
+    /// No request leaves the process. [`Middleware::get_logs_paginated`] is not shadowed: its
+    /// `LogQuery` is bound to a JSON-RPC provider, so calling it on this middleware still queries
+    /// the inner provider.
+    pub fn logs_paginated<'a>(&'a self, filter: &EthersFilter, page_size: u64) -> LogStream<'a, M> {
+        let filter = filter.clone();
+        let page_size = page_size.max(1);
+
+        let (from_block, to_block) = match &filter.block_option {
+            EthersFilterBlockOption::Range { from_block, to_block } => (*from_block, *to_block),
+            EthersFilterBlockOption::AtBlockHash(_) => {
+                // a single block always fits in a single page
+                let page = async move {
+                    let logs: Vec<EthersLog> =
+                        self.reth_filter.logs(filter.into_reth()).await?.into_ethers();
+                    Ok::<_, RethMiddlewareError<M>>(logs)
+                };
+                return stream::once(page)
+                    .map_ok(|logs| stream::iter(logs.into_iter().map(Ok)))
+                    .try_flatten()
+                    .boxed()
+            }
+        };
+
+        // the range is resolved lazily, so that `latest` refers to the tip at the first poll
+        stream::try_unfold(Pages::Unresolved, move |pages| {
+            let filter = filter.clone();
+            async move {
+                let (start, end, mut size) = match pages {
+                    Pages::Unresolved => (
+                        self.resolve_block_number(from_block)?,
+                        self.resolve_block_number(to_block)?,
+                        page_size,
+                    ),
+                    Pages::From { start, end, size } => (start, end, size),
+                    Pages::Done => return Ok(None),
+                };
+                if start > end {
+                    return Ok(None)
+                }
+
+                let (logs, page_end): (Vec<EthersLog>, u64) = loop {
+                    let page_end = start.saturating_add(size - 1).min(end);
+                    let page = filter.clone().from_block(start).to_block(page_end);
+                    match self
+                        .reth_filter
+                        .logs(page.into_reth())
+                        .await
+                        .map_err(RethMiddlewareError::<M>::from)
+                    {
+                        Ok(logs) => break (logs.into_ethers(), page_end),
+                        Err(RethMiddlewareError::InvalidParams(message))
+                            if page_end > start && message.starts_with(MAX_RESULTS_EXCEEDED) =>
+                        {
+                            size = (page_end - start + 1) / 2;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                };
+
+                let next = if page_end < end {
+                    Pages::From { start: page_end + 1, end, size }
+                } else {
+                    Pages::Done
+                };
+                Ok::<_, RethMiddlewareError<M>>(Some((logs, next)))
+            }
+        })
+        .map_ok(|logs| stream::iter(logs.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    /// Streams the hashes of new canonical blocks.
+    ///
+    /// Blocks are reported as the head follower sees the reth node writing to the database
+    /// commit them, so no node connection is needed. This shadows [`Middleware::watch_blocks`],
+    /// which polls the inner provider.
+    pub fn watch_blocks(&self) -> BlockHashStream {
+        followed_block_hashes(self.head_update_sender.subscribe()).boxed()
+    }
+
+    /// Streams the headers of new canonical blocks.
+    ///
+    /// This shadows [`Middleware::subscribe_blocks`], which requires a pubsub connection.
+    pub fn subscribe_blocks(&self) -> BlockStream<'_, M> {
+        self.watch_blocks()
+            .then(move |hash| async move {
+                let block: Option<EthersBlock<EthersH256>> =
+                    self.reth_api.block_by_hash(hash.into(), false).await?.into_ethers();
+                Ok::<_, RethMiddlewareError<M>>(block)
+            })
+            // a block that was reorged out before it could be read is skipped
+            .filter_map(|block| async move { block.transpose() })
+            .boxed()
+    }
+
+    /// Streams the logs matching `filter` that are emitted by new canonical blocks.
+    ///
+    /// The block range of `filter` is ignored. This shadows [`Middleware::subscribe_logs`],
+    /// which requires a pubsub connection.
+    pub fn subscribe_logs<'a>(&'a self, filter: &EthersFilter) -> LogStream<'a, M> {
+        let filter = filter.clone();
+
+        self.watch_blocks()
+            .then(move |hash| {
+                let filter = filter.clone().at_block_hash(hash);
+                async move {
+                    let logs: Vec<EthersLog> =
+                        self.reth_filter.logs(filter.into_reth()).await?.into_ethers();
+                    Ok::<_, RethMiddlewareError<M>>(logs)
+                }
+            })
+            .map_ok(|logs| stream::iter(logs.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+}
+
+/// The message reth's log filter rejects a query with when it matches more than
+/// `max_logs_per_response` logs.
+const MAX_RESULTS_EXCEEDED: &str = "query exceeds max results";
+
+/// The pages of a paginated log query left to load.
+#[derive(Debug, Clone, Copy)]
+enum Pages {
+    /// The block range was not resolved yet.
+    Unresolved,
+    /// The pages from `start` to `end`, inclusive, of `size` blocks each.
+    From { start: u64, end: u64, size: u64 },
+    /// The last block of the range was read.
+    Done,
+}
+
+/// Turns head updates of the database follower into the hashes of the committed blocks, in order.
+fn followed_block_hashes(receiver: Receiver<HeadUpdate>) -> impl Stream<Item = EthersH256> + Send {
+    broadcast_stream(receiver).flat_map(|update| {
+        stream::iter(update.committed.into_iter().map(|block| block.hash.into_ethers()))
+    })
+}
+
+/// Turns a broadcast receiver into a stream.
+///
+/// Messages missed because the receiver lagged behind are skipped.
+fn broadcast_stream<T: Clone + Send>(receiver: Receiver<T>) -> impl Stream<Item = T> + Send {
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => return Some((message, receiver)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reth_primitives::{BlockNumHash, H256};
+    use tokio::sync::broadcast;
+
+    #[tokio::test]
+    async fn observes_followed_blocks() {
+        let (sender, receiver) = broadcast::channel(4);
+        let hashes = followed_block_hashes(receiver);
+
+        let block = |number| BlockNumHash::new(number, H256::from_low_u64_be(number));
+        sender
+            .send(HeadUpdate {
+                old_tip: block(1),
+                new_tip: block(3),
+                reverted: Vec::new(),
+                committed: vec![block(2), block(3)],
+            })
+            .unwrap();
+        drop(sender);
+
+        let hashes: Vec<EthersH256> = hashes.collect().await;
+        assert_eq!(hashes, vec![EthersH256::from_low_u64_be(2), EthersH256::from_low_u64_be(3)]);
+    }
+}
@@ -4,5 +4,6 @@ pub mod call;
 pub mod fee;
 pub mod filter;
 pub mod log;
+pub mod state;
 pub mod trace;
 pub mod transaction;
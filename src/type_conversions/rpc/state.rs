@@ -0,0 +1,188 @@
+use crate::type_conversions::{ToEthers, ToReth};
+use std::collections::HashMap;
+
+use ethers::types::{
+    spoof::{Account as EthersAccountOverride, State as EthersStateOverride, Storage},
+    Address as EthersAddress, BlockOverrides as EthersBlockOverrides, H256 as EthersH256,
+};
+use reth_primitives::{H256, U256};
+use reth_rpc_types::{
+    state::{AccountOverride, StateOverride},
+    BlockOverrides,
+};
+
+/// StateOverride (ethers) -> (reth)
+///
+/// The ethers state override keeps its accounts private, but serializes as the map of account
+/// overrides, so they are read back from that map.
+pub(crate) fn state_override_into_reth(
+    state: EthersStateOverride,
+) -> Result<StateOverride, serde_json::Error> {
+    let accounts: HashMap<EthersAddress, EthersAccountOverride> =
+        serde_json::from_value(serde_json::to_value(state)?)?;
+
+    Ok(accounts
+        .into_iter()
+        .map(|(address, account)| (address.into_reth(), account.into_reth()))
+        .collect())
+}
+
+/// StateOverride (reth) -> (ethers)
+impl ToEthers<EthersStateOverride> for StateOverride {
+    fn into_ethers(self) -> EthersStateOverride {
+        let mut state = EthersStateOverride::default();
+        for (address, account) in self {
+            *state.account(address.into_ethers()) = account.into_ethers();
+        }
+        state
+    }
+}
+
+/// AccountOverride (ethers) -> (reth)
+impl ToReth<AccountOverride> for EthersAccountOverride {
+    fn into_reth(self) -> AccountOverride {
+        let (state, state_diff) = match self.storage {
+            Some(Storage::Replace(state)) => (Some(storage_into_reth(state)), None),
+            Some(Storage::Diff(state_diff)) => (None, Some(storage_into_reth(state_diff))),
+            None => (None, None),
+        };
+
+        AccountOverride {
+            nonce: self.nonce.into_reth(),
+            balance: self.balance.into_reth(),
+            code: self.code.into_reth(),
+            state,
+            state_diff,
+        }
+    }
+}
+
+/// AccountOverride (reth) -> (ethers)
+///
+/// The ethers override holds either a full state or a diff, reth rejects overrides with both.
+impl ToEthers<EthersAccountOverride> for AccountOverride {
+    fn into_ethers(self) -> EthersAccountOverride {
+        let storage = match (self.state, self.state_diff) {
+            (Some(state), _) => Some(Storage::Replace(storage_into_ethers(state))),
+            (None, Some(state_diff)) => Some(Storage::Diff(storage_into_ethers(state_diff))),
+            (None, None) => None,
+        };
+
+        EthersAccountOverride {
+            nonce: self.nonce.into_ethers(),
+            balance: self.balance.into_ethers(),
+            code: self.code.into_ethers(),
+            storage,
+        }
+    }
+}
+
+/// Storage slots are overridden with words in ethers and with numbers in reth.
+fn storage_into_reth(storage: HashMap<EthersH256, EthersH256>) -> HashMap<H256, U256> {
+    storage
+        .into_iter()
+        .map(|(slot, value)| (slot.into_reth(), U256::from_be_bytes(value.0)))
+        .collect()
+}
+
+fn storage_into_ethers(storage: HashMap<H256, U256>) -> HashMap<EthersH256, EthersH256> {
+    storage
+        .into_iter()
+        .map(|(slot, value)| (slot.into_ethers(), EthersH256(value.to_be_bytes::<32>())))
+        .collect()
+}
+
+// -----------------------------------------------
+
+/// BlockOverrides (ethers) -> (reth)
+impl ToReth<BlockOverrides> for EthersBlockOverrides {
+    fn into_reth(self) -> BlockOverrides {
+        BlockOverrides {
+            number: self.number.into_reth(),
+            difficulty: self.difficulty.into_reth(),
+            time: self.time.into_reth(),
+            gas_limit: self.gas_limit.into_reth(),
+            coinbase: self.coinbase.into_reth(),
+            random: self.random.into_reth(),
+            base_fee: self.base_fee.into_reth(),
+            block_hash: None,
+        }
+    }
+}
+
+/// BlockOverrides (reth) -> (ethers)
+///
+/// Block hash overrides have no ethers counterpart and are dropped.
+impl ToEthers<EthersBlockOverrides> for BlockOverrides {
+    fn into_ethers(self) -> EthersBlockOverrides {
+        EthersBlockOverrides {
+            number: self.number.into_ethers(),
+            difficulty: self.difficulty.into_ethers(),
+            time: self.time.into_ethers(),
+            gas_limit: self.gas_limit.into_ethers(),
+            coinbase: self.coinbase.into_ethers(),
+            random: self.random.into_ethers(),
+            base_fee: self.base_fee.into_ethers(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Bytes as EthersBytes, U256 as EthersU256};
+    use reth_primitives::{Address, U64};
+
+    #[test]
+    fn state_override_round_trip() {
+        let address = EthersAddress::repeat_byte(1);
+        let other = EthersAddress::repeat_byte(2);
+        let mut state = EthersStateOverride::default();
+        state
+            .account(address)
+            .nonce(7u64.into())
+            .balance(EthersU256::exp10(18))
+            .code(EthersBytes::from(vec![0x60, 0x00]))
+            .store(EthersH256::from_low_u64_be(1), EthersH256::from_low_u64_be(17));
+        *state.account(other) = EthersAccountOverride {
+            storage: Some(Storage::Replace(HashMap::from([(
+                EthersH256::from_low_u64_be(2),
+                EthersH256::repeat_byte(0xff),
+            )]))),
+            ..Default::default()
+        };
+
+        let reth_state = state_override_into_reth(state.clone()).unwrap();
+        let reth_address: Address = address.into_reth();
+        let reth_other: Address = other.into_reth();
+        let account = &reth_state[&reth_address];
+        assert_eq!(account.nonce, Some(U64::from(7)));
+        assert_eq!(account.state_diff.as_ref().unwrap()[&H256::from_low_u64_be(1)], U256::from(17));
+        assert!(reth_state[&reth_other].state.is_some());
+
+        let round_trip: EthersStateOverride = reth_state.into_ethers();
+        assert_eq!(round_trip, state);
+    }
+
+    #[test]
+    fn block_overrides_round_trip() {
+        let overrides = EthersBlockOverrides {
+            number: Some(10u64.into()),
+            difficulty: Some(1u64.into()),
+            time: Some(1_700_000_000u64.into()),
+            gas_limit: Some(30_000_000u64.into()),
+            coinbase: Some(EthersAddress::repeat_byte(3)),
+            random: Some(EthersH256::repeat_byte(4)),
+            base_fee: Some(7u64.into()),
+        };
+
+        let reth_overrides: BlockOverrides = overrides.clone().into_reth();
+        assert_eq!(reth_overrides.number, Some(U256::from(10)));
+
+        let round_trip: EthersBlockOverrides = reth_overrides.into_ethers();
+        assert_eq!(round_trip, overrides);
+
+        let empty: EthersBlockOverrides = BlockOverrides::default().into_ethers();
+        assert_eq!(empty, EthersBlockOverrides::default());
+    }
+}
@@ -1,13 +1,14 @@
-use crate::type_conversions::{ToEthers, ToReth};
+use crate::type_conversions::{rpc::state::state_override_into_reth, ToEthers, ToReth};
 
 use ethers::types::{
     AccountDiff as EthersAccountDiff, AccountState as EthersAccountState, Action as EthersAction,
-    ActionType as EthersActionType, BlockTrace as EthersBlockTrace, Call as EthersCall,
-    CallFrame as EthersCallFrame, CallLogFrame as EthersCallLogFrame,
-    CallResult as EthersCallResult, CallType as EthersCallType, ChangedType as EthersChangedType,
-    Create as EthersCreate, CreateResult as EthersCreateResult, DefaultFrame as EthersDefaultFrame,
-    Diff as EthersDiff, DiffMode as EthersDiffMode, ExecutedInstruction,
-    FourByteFrame as EthersFourByteFrame,
+    ActionType as EthersActionType, Address as EthersAddress, BlockNumber as EthersBlockNumber,
+    BlockTrace as EthersBlockTrace, Call as EthersCall, CallFrame as EthersCallFrame,
+    CallLogFrame as EthersCallLogFrame, CallResult as EthersCallResult, CallType as EthersCallType,
+    ChangedType as EthersChangedType, Create as EthersCreate, CreateResult as EthersCreateResult,
+    DefaultFrame as EthersDefaultFrame, Diff as EthersDiff, DiffMode as EthersDiffMode,
+    ExecutedInstruction, FourByteFrame as EthersFourByteFrame,
+    GethDebugBuiltInTracerConfig as EthersGethDebugBuiltInTracerConfig,
     GethDebugBuiltInTracerType as EthersGethDebugBuiltInTracerType,
     GethDebugTracerConfig as EthersGethDebugTracerConfig,
     GethDebugTracerType as EthersGethDebugTracerType,
@@ -18,16 +19,22 @@ use ethers::types::{
     PreStateMode as EthersPreStateMode, Res as EthersRes, Reward as EthersReward,
     RewardType as EthersRewardType, StateDiff as EthersStateDiff, StorageDiff as EthersStorageDiff,
     StructLog as EthersStructLog, Suicide as EthersSuicide, Trace as EthersTrace,
-    TraceType as EthersTraceType, TransactionTrace as EthersTransactionTrace,
-    VMExecutedOperation as EthersVMExecutedOperation, VMOperation as EthersVMOperation,
-    VMTrace as EthersVMTrace,
+    TraceFilter as EthersTraceFilter, TraceType as EthersTraceType,
+    TransactionTrace as EthersTransactionTrace, VMExecutedOperation as EthersVMExecutedOperation,
+    VMOperation as EthersVMOperation, VMTrace as EthersVMTrace,
 };
+use serde::Deserialize;
+
 use reth_revm::primitives::bitvec::macros::internal::funty::Fundamental;
-use reth_rpc_types::trace::parity::{
-    AccountDiff, Action, CallAction, CallOutput, CallType, ChangedType, CreateAction, CreateOutput,
-    Delta, LocalizedTransactionTrace, MemoryDelta, RewardAction, RewardType, SelfdestructAction,
-    StateDiff, StorageDelta, TraceOutput, TraceResults, TraceResultsWithTransactionHash, TraceType,
-    TransactionTrace, VmExecutedOperation, VmInstruction, VmTrace,
+use reth_rpc_types::trace::{
+    filter::TraceFilter,
+    parity::{
+        AccountDiff, Action, CallAction, CallOutput, CallType, ChangedType, CreateAction,
+        CreateOutput, Delta, LocalizedTransactionTrace, MemoryDelta, RewardAction, RewardType,
+        SelfdestructAction, StateDiff, StorageDelta, TraceOutput, TraceResults,
+        TraceResultsWithTransactionHash, TraceType, TransactionTrace, VmExecutedOperation,
+        VmInstruction, VmTrace,
+    },
 };
 
 use reth_rpc_types::trace::geth::{
@@ -37,13 +44,71 @@ use reth_rpc_types::trace::geth::{
     StructLog,
 };
 
+/// The fields of an ethers trace filter, which keeps them private but serializes them in the
+/// `trace_filter` JSON-RPC encoding.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TraceFilterFields {
+    from_block: Option<EthersBlockNumber>,
+    to_block: Option<EthersBlockNumber>,
+    from_address: Option<Vec<EthersAddress>>,
+    to_address: Option<Vec<EthersAddress>>,
+    after: Option<u64>,
+    count: Option<u64>,
+}
+
+/// A reth trace filter whose block range is still the one of the ethers filter, which may hold
+/// tags that only the database can resolve to numbers.
+#[derive(Debug, Clone)]
+pub(crate) struct UnresolvedTraceFilter {
+    pub(crate) from_block: Option<EthersBlockNumber>,
+    pub(crate) to_block: Option<EthersBlockNumber>,
+    /// The filter without its block range.
+    pub(crate) filter: TraceFilter,
+}
+
+/// TraceFilter (ethers) -> (reth)
+impl TryFrom<EthersTraceFilter> for UnresolvedTraceFilter {
+    type Error = serde_json::Error;
+
+    fn try_from(filter: EthersTraceFilter) -> Result<Self, Self::Error> {
+        let fields: TraceFilterFields = serde_json::from_value(serde_json::to_value(filter)?)?;
+
+        Ok(Self {
+            from_block: fields.from_block,
+            to_block: fields.to_block,
+            filter: TraceFilter {
+                from_block: None,
+                to_block: None,
+                from_address: fields.from_address.unwrap_or_default().into_reth(),
+                to_address: fields.to_address.unwrap_or_default().into_reth(),
+                after: fields.after,
+                count: fields.count,
+            },
+        })
+    }
+}
+
 /// GethDebugTracingCallOptions (ethers) -> (reth)
-impl ToReth<GethDebugTracingCallOptions> for EthersDebugTracingCallOptions {
-    fn into_reth(self) -> GethDebugTracingCallOptions {
-        GethDebugTracingCallOptions {
-            tracing_options: GethDebugTracingOptions::default(),
-            state_overrides: None,
-            block_overrides: None,
+///
+/// Fails like [`state_override_into_reth`] if the state overrides cannot be read.
+pub(crate) fn tracing_call_options_into_reth(
+    options: EthersDebugTracingCallOptions,
+) -> Result<GethDebugTracingCallOptions, serde_json::Error> {
+    Ok(GethDebugTracingCallOptions {
+        tracing_options: options.tracing_options.into_reth(),
+        state_overrides: options.state_overrides.map(state_override_into_reth).transpose()?,
+        block_overrides: options.block_overrides.into_reth(),
+    })
+}
+
+/// GethDebugTracingCallOptions (reth) -> (ethers)
+impl ToEthers<EthersDebugTracingCallOptions> for GethDebugTracingCallOptions {
+    fn into_ethers(self) -> EthersDebugTracingCallOptions {
+        EthersDebugTracingCallOptions {
+            tracing_options: self.tracing_options.into_ethers(),
+            state_overrides: self.state_overrides.into_ethers(),
+            block_overrides: self.block_overrides.into_ethers(),
         }
     }
 }
@@ -73,6 +138,25 @@ impl ToReth<GethDebugTracingOptions> for EthersDebugTracingOptions {
     }
 }
 
+/// GethDebugTracingOptions (reth) -> (ethers)
+///
+/// The `disableMemory`, `disableReturnData`, `debug` and `limit` options that are only known to
+/// reth are dropped.
+impl ToEthers<EthersDebugTracingOptions> for GethDebugTracingOptions {
+    fn into_ethers(self) -> EthersDebugTracingOptions {
+        let tracer_config = tracer_config_into_ethers(self.tracer.as_ref(), self.tracer_config);
+        EthersDebugTracingOptions {
+            disable_storage: self.config.disable_storage,
+            disable_stack: self.config.disable_stack,
+            enable_memory: self.config.enable_memory,
+            enable_return_data: self.config.enable_return_data,
+            tracer: self.tracer.and_then(ToEthers::into_ethers),
+            tracer_config,
+            timeout: self.timeout,
+        }
+    }
+}
+
 /// GethDebugTracerType (ethers) -> (reth)
 impl ToReth<GethDebugTracerType> for EthersGethDebugTracerType {
     fn into_reth(self) -> GethDebugTracerType {
@@ -92,6 +176,47 @@ impl ToReth<GethDebugTracerConfig> for EthersGethDebugTracerConfig {
     }
 }
 
+/// GethDebugTracerType (reth) -> (ethers)
+///
+/// `None` for the built-in tracers ethers does not know, both sides share the tracer names.
+impl ToEthers<Option<EthersGethDebugTracerType>> for GethDebugTracerType {
+    fn into_ethers(self) -> Option<EthersGethDebugTracerType> {
+        serde_json::from_value(serde_json::to_value(self).ok()?).ok()
+    }
+}
+
+/// GethDebugTracerConfig (reth) -> (ethers)
+///
+/// The ethers config is untagged and would read any object as a call tracer config, so the
+/// variant is chosen by the `tracer` it configures. Configs of other tracers are kept as JSON.
+fn tracer_config_into_ethers(
+    tracer: Option<&GethDebugTracerType>,
+    config: GethDebugTracerConfig,
+) -> Option<EthersGethDebugTracerConfig> {
+    if config.0.is_null() {
+        return None
+    }
+
+    let built_in = match tracer {
+        Some(GethDebugTracerType::BuiltInTracer(GethDebugBuiltInTracerType::CallTracer)) => {
+            serde_json::from_value(config.0.clone())
+                .ok()
+                .map(EthersGethDebugBuiltInTracerConfig::CallTracer)
+        }
+        Some(GethDebugTracerType::BuiltInTracer(GethDebugBuiltInTracerType::PreStateTracer)) => {
+            serde_json::from_value(config.0.clone())
+                .ok()
+                .map(EthersGethDebugBuiltInTracerConfig::PreStateTracer)
+        }
+        _ => None,
+    };
+
+    Some(match built_in {
+        Some(config) => EthersGethDebugTracerConfig::BuiltInTracer(config),
+        None => EthersGethDebugTracerConfig::JsTracer(config.0),
+    })
+}
+
 /// GethDebugBuiltInTracerType (ethers) -> (reth)
 impl ToReth<GethDebugBuiltInTracerType> for EthersGethDebugBuiltInTracerType {
     fn into_reth(self) -> GethDebugBuiltInTracerType {
@@ -401,18 +526,20 @@ impl ToReth<LocalizedTransactionTrace> for EthersTrace {
 }
 
 /// LocalizedTransactionTrace (reth) -> EthersTrace (ethers)
-impl ToEthers<EthersTrace> for LocalizedTransactionTrace {
-    fn into_ethers(self) -> EthersTrace {
+///
+/// `None` for traces of pending transactions, which have no block yet.
+impl ToEthers<Option<EthersTrace>> for LocalizedTransactionTrace {
+    fn into_ethers(self) -> Option<EthersTrace> {
         let action = self.trace.action.into_ethers();
-        EthersTrace {
+        Some(EthersTrace {
             action: action.clone(),
             result: self.trace.result.clone().into_ethers(),
             trace_address: self.trace.trace_address,
             subtraces: self.trace.subtraces,
             transaction_position: self.transaction_position.map(|x| x as usize),
             transaction_hash: self.transaction_hash.into_ethers(),
-            block_number: self.block_number.unwrap(),
-            block_hash: self.block_hash.into_ethers().unwrap(),
+            block_number: self.block_number?,
+            block_hash: self.block_hash?.into_ethers(),
             action_type: match action {
                 EthersAction::Call(_) => EthersActionType::Call,
                 EthersAction::Create(_) => EthersActionType::Create,
@@ -420,7 +547,7 @@ impl ToEthers<EthersTrace> for LocalizedTransactionTrace {
                 EthersAction::Reward(_) => EthersActionType::Reward,
             },
             error: self.trace.error,
-        }
+        })
     }
 }
 
@@ -809,3 +936,108 @@ where
         EthersChangedType { from: self.from.into_ethers(), to: self.to.into_ethers() }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{
+        spoof, BlockOverrides as EthersBlockOverrides, CallConfig, GethDebugBuiltInTracerConfig,
+        PreStateConfig, H256 as EthersH256,
+    };
+
+    #[test]
+    fn tracing_call_options_round_trip() {
+        let mut state_overrides = spoof::state();
+        state_overrides
+            .account(EthersAddress::repeat_byte(1))
+            .balance(1_000u64.into())
+            .store(EthersH256::from_low_u64_be(1), EthersH256::from_low_u64_be(2));
+
+        let options = EthersDebugTracingCallOptions {
+            tracing_options: EthersDebugTracingOptions {
+                disable_storage: Some(true),
+                enable_return_data: Some(true),
+                tracer: Some(EthersGethDebugTracerType::BuiltInTracer(
+                    EthersGethDebugBuiltInTracerType::CallTracer,
+                )),
+                tracer_config: Some(EthersGethDebugTracerConfig::BuiltInTracer(
+                    GethDebugBuiltInTracerConfig::CallTracer(CallConfig {
+                        only_top_call: Some(true),
+                        with_log: Some(false),
+                    }),
+                )),
+                timeout: Some("5s".to_string()),
+                ..Default::default()
+            },
+            state_overrides: Some(state_overrides),
+            block_overrides: Some(EthersBlockOverrides {
+                number: Some(4u64.into()),
+                base_fee: Some(1u64.into()),
+                ..Default::default()
+            }),
+        };
+
+        let reth_options = tracing_call_options_into_reth(options.clone()).unwrap();
+        assert_eq!(
+            reth_options.tracing_options.tracer,
+            Some(GethDebugTracerType::BuiltInTracer(GethDebugBuiltInTracerType::CallTracer))
+        );
+        assert!(reth_options.state_overrides.is_some());
+        assert!(reth_options.block_overrides.is_some());
+
+        let round_trip: EthersDebugTracingCallOptions = reth_options.into_ethers();
+        assert_eq!(round_trip, options);
+    }
+
+    #[test]
+    fn trace_filter_with_tags() {
+        let from = EthersAddress::repeat_byte(1);
+        let filter = EthersTraceFilter::default()
+            .from_block(EthersBlockNumber::Earliest)
+            .to_block(EthersBlockNumber::Latest)
+            .from_address(vec![from])
+            .after(2)
+            .count(3);
+
+        let unresolved = UnresolvedTraceFilter::try_from(filter).unwrap();
+        assert_eq!(unresolved.from_block, Some(EthersBlockNumber::Earliest));
+        assert_eq!(unresolved.to_block, Some(EthersBlockNumber::Latest));
+        assert_eq!(unresolved.filter.from_block, None);
+        assert_eq!(unresolved.filter.from_address.len(), 1);
+        assert!(unresolved.filter.to_address.is_empty());
+        assert_eq!((unresolved.filter.after, unresolved.filter.count), (Some(2), Some(3)));
+
+        let unresolved = UnresolvedTraceFilter::try_from(EthersTraceFilter::default()).unwrap();
+        assert_eq!(unresolved.from_block, None);
+        assert_eq!(unresolved.to_block, None);
+    }
+
+    #[test]
+    fn prestate_tracer_config_round_trip() {
+        let options = EthersDebugTracingOptions {
+            tracer: Some(EthersGethDebugTracerType::BuiltInTracer(
+                EthersGethDebugBuiltInTracerType::PreStateTracer,
+            )),
+            tracer_config: Some(EthersGethDebugTracerConfig::BuiltInTracer(
+                GethDebugBuiltInTracerConfig::PreStateTracer(PreStateConfig {
+                    diff_mode: Some(true),
+                }),
+            )),
+            ..Default::default()
+        };
+
+        let reth_options: GethDebugTracingOptions = options.clone().into_reth();
+        assert_eq!(reth_options.tracer_config.0, serde_json::json!({ "diffMode": true }));
+
+        let round_trip: EthersDebugTracingOptions = reth_options.into_ethers();
+        assert_eq!(round_trip, options);
+    }
+
+    #[test]
+    fn default_tracing_options_round_trip() {
+        let reth_options: GethDebugTracingOptions =
+            EthersDebugTracingOptions::default().into_reth();
+        let round_trip: EthersDebugTracingOptions = reth_options.into_ethers();
+        assert_eq!(round_trip, EthersDebugTracingOptions::default());
+    }
+}
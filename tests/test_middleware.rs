@@ -10,36 +10,60 @@ mod test_utils;
 // `reth node --chain goerli --datadir ./testdata --http --http.api all --debug.tip
 // 0xe9006d7148f879e1af79d12ba532d061e160ded8f9066c3d74c9724f65366d94`
 mod tests {
-    use std::path::{Path, PathBuf};
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
 
     use ethers::{
         prelude::k256::ecdsa::SigningKey,
-        providers::Middleware,
+        providers::{Middleware, Provider},
         signers::Wallet,
         types::{
+            spoof,
             transaction::{
                 eip2718::TypedTransaction as EthersTypedTransaction,
                 eip2930::AccessListWithGasUsed as EthersAccessListWithGasUsed,
             },
             Address as EthersAddress, Block as EthersBlock, BlockId as EthersBlockId,
-            BlockNumber as EthersBlockNumber, BlockTrace as EthersBlockTrace, Bytes as EthersBytes,
-            Eip1559TransactionRequest, FeeHistory as EthersFeeHistory, Filter as EthersFilter,
+            BlockNumber as EthersBlockNumber, BlockOverrides as EthersBlockOverrides,
+            BlockTrace as EthersBlockTrace, Bytes as EthersBytes, Eip1559TransactionRequest,
+            FeeHistory as EthersFeeHistory, Filter as EthersFilter,
             FilterBlockOption as EthersFilterBlockOption, GethTrace as EthersGethTrace,
             Log as EthersLog, NameOrAddress as EthersNameOrAddress, Trace as EthersTrace,
-            TraceType as EthersTraceType, Transaction as EthersTransaction,
-            TransactionReceipt as EthersTransactionReceipt,
+            TraceFilter as EthersTraceFilter, TraceType as EthersTraceType,
+            Transaction as EthersTransaction, TransactionReceipt as EthersTransactionReceipt,
             TransactionRequest as EthersTransactionRequest, TxHash as EthersTxHash,
             H256 as EthersH256, U256 as EthersU256,
         },
     };
 
-    use reth_primitives::{DEV, MAINNET, U64};
+    use ethers_reth::{
+        builder::RethMiddlewareBuilder,
+        call_many::{CallManyBundle, StateContext},
+        database::RethDatabase,
+        fallback::FallbackPolicy,
+        overrides::CallOverrides,
+        provider::{OfflineClient, RethProvider},
+        shadow::{ShadowConfig, ShadowMiddleware},
+        simulate::SimulatedBlockSpec,
+        type_conversions::{ToEthers, ToReth},
+        RethMiddleware, RethMiddlewareError,
+    };
+    use futures::TryStreamExt;
+    use reth_primitives::{Address, DEV, MAINNET, U64};
+    use reth_revm::{
+        db::DatabaseRef,
+        primitives::{ResultAndState, TransactTo, U256},
+        Database, EVM,
+    };
 
     use serial_test::serial;
+    use tokio::runtime::Handle;
 
     use pretty_assertions::assert_eq;
 
-    use crate::test_utils::{spawn_reth_middleware, MAINNET_HTTP_URL};
+    use crate::test_utils::{spawn_http_provider, spawn_reth_middleware, MAINNET_HTTP_URL};
 
     const BLOCK_NUMBER: u64 = 3;
     const BLOCK_HASH: &str = "0x5ba8efbbe87e1f50f06bea90637e360bcf126e38d37d3d31bd0e5ed62d37fc7b";
@@ -97,6 +121,336 @@ mod tests {
         assert_eq!(expected_call_result, call_result);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_call_with_overrides() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let block_id: EthersBlockId = BLOCK_NUMBER.into();
+        let contract = EthersAddress::repeat_byte(0xaa);
+        let call_transaction: EthersTypedTransaction = Eip1559TransactionRequest::new()
+            .from(WALLET_ADDRESS.parse::<EthersAddress>().unwrap())
+            .to(contract)
+            .into();
+
+        // NUMBER PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let mut state = spoof::state();
+        state.account(contract).code("0x4360005260206000f3".parse().unwrap());
+        let overrides = CallOverrides::new()
+            .state(state)
+            .block(EthersBlockOverrides { number: Some(100u64.into()), ..Default::default() });
+
+        let call_result = reth_middleware
+            .call_with_overrides(&call_transaction, Some(block_id), overrides.clone())
+            .await
+            .unwrap();
+        assert_eq!(EthersU256::from_big_endian(&call_result), 100u64.into());
+
+        let gas = reth_middleware
+            .estimate_gas_with_overrides(&call_transaction, Some(block_id), overrides)
+            .await
+            .unwrap();
+        assert!(gas > 21_000u64.into() && gas < 22_000u64.into());
+
+        // PUSH20 0xbb..bb EXTCODESIZE STOP
+        let loaded = EthersAddress::repeat_byte(0xbb);
+        let mut state = spoof::state();
+        state
+            .account(contract)
+            .code(format!("0x73{}3b00", ethers::utils::hex::encode(loaded)).parse().unwrap());
+
+        let access_list = reth_middleware
+            .create_access_list_with_overrides(&call_transaction, Some(block_id), state.into())
+            .await
+            .unwrap();
+        assert_eq!(access_list.access_list.0.len(), 1);
+        assert_eq!(access_list.access_list.0[0].address, loaded);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_call_many() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let from: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let counter = EthersAddress::repeat_byte(0xaa);
+        let number = EthersAddress::repeat_byte(0xbb);
+        let call = |to: EthersAddress| -> EthersTypedTransaction {
+            EthersTransactionRequest::new().from(from).to(to).into()
+        };
+
+        // PUSH1 0 SLOAD PUSH1 1 ADD DUP1 PUSH1 0 SSTORE PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let mut state = spoof::state();
+        state.account(counter).code("0x6000546001018060005560005260206000f3".parse().unwrap());
+        // NUMBER PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        state.account(number).code("0x4360005260206000f3".parse().unwrap());
+
+        let bundles = vec![
+            CallManyBundle::new(vec![call(counter), call(counter)]),
+            CallManyBundle::new(vec![call(counter), call(number)]).block_overrides(
+                EthersBlockOverrides { number: Some(100u64.into()), ..Default::default() },
+            ),
+        ];
+
+        for state_context in
+            [StateContext::new(BLOCK_NUMBER), StateContext::at_transaction(BLOCK_NUMBER, 0)]
+        {
+            let responses = reth_middleware
+                .call_many(bundles.clone(), state_context, Some(state.clone()))
+                .await
+                .unwrap();
+
+            let values: Vec<Vec<EthersU256>> = responses
+                .iter()
+                .map(|bundle| {
+                    bundle
+                        .iter()
+                        .map(|response| {
+                            assert_eq!(response.error, None);
+                            EthersU256::from_big_endian(response.value.as_ref().unwrap())
+                        })
+                        .collect()
+                })
+                .collect();
+            assert_eq!(values, vec![vec![1.into(), 2.into()], vec![3.into(), 100.into()]]);
+        }
+
+        let past_end = StateContext::at_transaction(BLOCK_NUMBER, usize::MAX);
+        assert!(matches!(
+            reth_middleware.call_many(bundles, past_end, None).await,
+            Err(RethMiddlewareError::InvalidParams(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_simulate_blocks() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let from: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let counter = EthersAddress::repeat_byte(0xaa);
+        let header = EthersAddress::repeat_byte(0xbb);
+        let call = |to: EthersAddress| -> EthersTypedTransaction {
+            EthersTransactionRequest::new().from(from).to(to).into()
+        };
+
+        // PUSH1 0 SLOAD PUSH1 1 ADD DUP1 PUSH1 0 SSTORE PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let mut state = spoof::state();
+        state.account(counter).code("0x6000546001018060005560005260206000f3".parse().unwrap());
+        // TIMESTAMP NUMBER ADD PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        state.account(header).code("0x42430160005260206000f3".parse().unwrap());
+
+        let coinbase = EthersAddress::repeat_byte(0xcc);
+        let blocks = vec![
+            SimulatedBlockSpec::new(vec![call(counter), call(header)]).overrides(
+                CallOverrides::new().state(state).block(EthersBlockOverrides {
+                    time: Some(2_000_000_000u64.into()),
+                    coinbase: Some(coinbase),
+                    base_fee: Some(1_000u64.into()),
+                    ..Default::default()
+                }),
+            ),
+            SimulatedBlockSpec::new(vec![call(counter), call(header)]),
+        ];
+
+        let simulated = reth_middleware.simulate_blocks(blocks, BLOCK_NUMBER).await.unwrap();
+
+        assert_eq!(simulated.len(), 2);
+        assert_eq!(simulated[0].number, BLOCK_NUMBER + 1);
+        assert_eq!(simulated[1].number, BLOCK_NUMBER + 2);
+        assert_eq!(simulated[0].timestamp, 2_000_000_000);
+        assert_eq!(simulated[1].timestamp, 2_000_000_012);
+        assert_eq!(simulated[1].coinbase, coinbase);
+        // the second block is far below its gas target
+        assert!(simulated[1].base_fee < 1_000.into());
+
+        for (block, count) in simulated.iter().zip([1u64, 2]) {
+            assert!(block.calls.iter().all(|call| call.status));
+            assert_eq!(block.gas_used, block.calls.iter().map(|call| call.gas_used).sum::<u64>());
+            assert_eq!(EthersU256::from_big_endian(&block.calls[0].return_data), count.into());
+            assert_eq!(
+                EthersU256::from_big_endian(&block.calls[1].return_data),
+                (block.timestamp + block.number).into()
+            );
+        }
+
+        let backwards = vec![SimulatedBlockSpec::new(Vec::new()).overrides(EthersBlockOverrides {
+            number: Some(BLOCK_NUMBER.into()),
+            ..Default::default()
+        })];
+        assert!(matches!(
+            reth_middleware.simulate_blocks(backwards, BLOCK_NUMBER).await,
+            Err(RethMiddlewareError::InvalidParams(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_fork_session() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let from: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let to = EthersAddress::repeat_byte(0xaa);
+        let one_ether = EthersU256::exp10(18);
+
+        let mut session = reth_middleware.fork_at(BLOCK_NUMBER).await.unwrap();
+        assert_eq!(session.block_number(), BLOCK_NUMBER);
+        let balance = session.balance(from).unwrap();
+        assert_eq!(
+            balance,
+            reth_middleware.get_balance(from, Some(BLOCK_NUMBER.into())).await.unwrap()
+        );
+
+        let snapshot = session.snapshot();
+        let transfer: EthersTypedTransaction =
+            EthersTransactionRequest::new().from(from).to(to).value(one_ether).into();
+        let mut fees = EthersU256::zero();
+        for _ in 0..2 {
+            let result = session.apply_transaction(transfer.clone()).unwrap();
+            assert_eq!(result.gas_used, 21_000);
+            assert_eq!(result.error, None);
+            fees += result.gas_price * result.gas_used;
+        }
+
+        assert_eq!(session.transaction_count(), 2);
+        assert_eq!(session.balance(to).unwrap(), one_ether * 2);
+        assert_eq!(session.balance(from).unwrap(), balance - one_ether * 2 - fees);
+
+        let nested = session.snapshot();
+        session.apply_transaction(transfer.clone()).unwrap();
+        assert_eq!(session.balance(to).unwrap(), one_ether * 3);
+        assert!(session.revert(nested));
+        assert_eq!(session.transaction_count(), 2);
+        assert_eq!(session.balance(to).unwrap(), one_ether * 2);
+
+        // PUSH1 42 PUSH1 0 SSTORE
+        let mut state = spoof::state();
+        state.account(to).code("0x602a600055".parse().unwrap());
+        session.override_state(state).unwrap();
+        let call: EthersTypedTransaction = EthersTransactionRequest::new().from(from).to(to).into();
+        assert_eq!(session.call(&call).unwrap().error, None);
+        // calls do not keep their changes
+        assert_eq!(session.storage(to, EthersH256::zero()).unwrap(), EthersH256::zero());
+        assert_eq!(session.code(to).unwrap(), "0x602a600055".parse::<EthersBytes>().unwrap());
+
+        assert!(session.revert(snapshot));
+        assert!(!session.revert(snapshot));
+        assert_eq!(session.transaction_count(), 0);
+        assert_eq!(session.balance(from).unwrap(), balance);
+        assert_eq!(session.balance(to).unwrap(), EthersU256::zero());
+        assert_eq!(session.code(to).unwrap(), EthersBytes::default());
+
+        // the database is untouched
+        assert_eq!(
+            reth_middleware.get_balance(to, Some(BLOCK_NUMBER.into())).await.unwrap(),
+            EthersU256::zero()
+        );
+        drop(session);
+
+        let received = reth_middleware
+            .with_fork_at(BLOCK_NUMBER, move |session| {
+                session.apply_transaction(transfer)?;
+                session.balance(to)
+            })
+            .await
+            .unwrap();
+        assert_eq!(received, one_ether);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_reth_database() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let block_id: EthersBlockId = BLOCK_NUMBER.into();
+        let wallet: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let weth: EthersAddress = WETH_ADDRESS.parse().unwrap();
+
+        let mut database = reth_middleware.database_at(block_id).unwrap();
+        let by_number =
+            RethDatabase::at_block_number(reth_middleware.reth_client(), BLOCK_NUMBER).unwrap();
+
+        let account = DatabaseRef::basic(&database, wallet.into_reth()).unwrap().unwrap();
+        let balance: EthersU256 = account.balance.into_ethers();
+        assert_eq!(balance, reth_middleware.get_balance(wallet, Some(block_id)).await.unwrap());
+        assert_eq!(Database::basic(&mut database, wallet.into_reth()).unwrap(), Some(account));
+        assert_eq!(
+            DatabaseRef::basic(&by_number, wallet.into_reth()).unwrap(),
+            DatabaseRef::basic(&database, wallet.into_reth()).unwrap()
+        );
+
+        let weth_account = DatabaseRef::basic(&database, weth.into_reth()).unwrap().unwrap();
+        let code = DatabaseRef::code_by_hash(&database, weth_account.code_hash).unwrap();
+        assert_eq!(
+            EthersBytes::from(code.original_bytes()),
+            reth_middleware.get_code(weth, Some(block_id)).await.unwrap()
+        );
+
+        // deposit into WETH through revm, which reads every account from the database
+        let mut evm = EVM::new();
+        evm.database(database);
+        evm.env.tx.caller = wallet.into_reth();
+        evm.env.tx.transact_to = TransactTo::Call(weth.into_reth());
+        evm.env.tx.value = U256::from(1);
+        evm.env.tx.gas_limit = 100_000;
+        let ResultAndState { result, state } = evm.transact().unwrap();
+        assert!(result.is_success());
+        let weth_address: Address = weth.into_reth();
+        assert_eq!(state[&weth_address].info.balance, weth_account.balance + U256::from(1));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_call_bundle() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let block = reth_middleware.get_block(BLOCK_NUMBER).await.unwrap().unwrap();
+        let coinbase = block.author.unwrap();
+        let from: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let one_ether = EthersU256::exp10(18);
+
+        let transfer: EthersTypedTransaction =
+            EthersTransactionRequest::new().from(from).to(coinbase).value(one_ether).into();
+        // PUSH1 0 PUSH1 0 REVERT
+        let revert: EthersTypedTransaction = EthersTransactionRequest::new()
+            .from(from)
+            .data("0x60006000fd".parse::<EthersBytes>().unwrap())
+            .into();
+
+        // the sender cannot pay for the transfer
+        let invalid: EthersTypedTransaction = EthersTransactionRequest::new()
+            .from(EthersAddress::repeat_byte(0xdd))
+            .to(coinbase)
+            .value(one_ether)
+            .into();
+
+        let response = reth_middleware
+            .call_bundle(vec![transfer, revert, invalid], BLOCK_NUMBER)
+            .await
+            .unwrap();
+
+        assert_eq!(response.state_block_number, BLOCK_NUMBER);
+        assert_eq!(response.results.len(), 3);
+        assert_eq!(response.results[0].gas_used, 21_000);
+        assert_eq!(response.results[0].coinbase_payment, one_ether);
+        assert_eq!(response.results[0].revert, None);
+        assert_eq!(response.results[1].to, None);
+        assert_eq!(response.results[1].revert.as_deref(), Some("0x"));
+        assert_eq!(response.results[2].gas_used, 0);
+        assert!(response.results[2].error.as_deref().unwrap().starts_with("invalid transaction"));
+        assert_eq!(response.coinbase_diff, one_ether);
+        assert_eq!(
+            response.gas_used,
+            response.results.iter().map(|result| result.gas_used).sum::<u64>()
+        );
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_estimate_gas() {
@@ -121,6 +475,13 @@ mod tests {
         let expected_estimate_gas: EthersU256 = "0x5d9e".parse().unwrap();
 
         assert_eq!(expected_estimate_gas, estimate_gas);
+
+        // a plain transfer to an account without code needs the base transaction gas
+        let transfer = &EthersTypedTransaction::Eip1559(
+            Eip1559TransactionRequest::new().from(from).to(EthersAddress::repeat_byte(0x42)),
+        );
+        let estimate_gas = reth_middleware.estimate_gas(transfer, Some(block_id)).await.unwrap();
+        assert_eq!(estimate_gas, 21_000u64.into());
     }
 
     #[tokio::test]
@@ -253,6 +614,133 @@ mod tests {
         assert_eq!(expected_chainid, chainid);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_shutdown_and_reopen() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+        let shutdown_handle = reth_middleware.shutdown_handle().clone();
+
+        // a remaining handle stops the tasks but does not wait for the database
+        reth_middleware.clone().shutdown().await.unwrap();
+        assert!(shutdown_handle.is_stopped());
+        drop(shutdown_handle);
+
+        // the last handle waits until the database is closed
+        reth_middleware.shutdown().await.unwrap();
+
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+        let block_number = reth_middleware.get_block_number().await.unwrap();
+
+        let expected_block_number: U64 = 5.into();
+
+        assert_eq!(expected_block_number, block_number);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_standalone_provider() {
+        let reth_provider =
+            RethProvider::standalone(get_db_dir(), Handle::current(), DEV.clone()).unwrap();
+
+        let block_number = reth_provider.get_block_number().await.unwrap();
+        let expected_block_number: U64 = 5.into();
+        assert_eq!(expected_block_number, block_number);
+
+        let err = reth_provider.get_net_version().await.unwrap_err();
+        assert_eq!(err.to_string(), "net_version is not supported without a network provider");
+        assert!(err.is_unsupported());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_resolve_name_locally() {
+        let reth_provider =
+            RethProvider::standalone(get_db_dir(), Handle::current(), DEV.clone()).unwrap();
+
+        // the dev chain has no ENS registry, and the standalone provider has no network fallback
+        let err = reth_provider.resolve_name("vitalik.eth").await.unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::EnsError(name) if name == "vitalik.eth"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_fallback_to_inner() {
+        let reth_provider =
+            RethMiddlewareBuilder::new(get_db_dir(), Handle::current(), DEV.clone())
+                .fallback_policy(FallbackPolicy::all())
+                .build_standalone()
+                .unwrap();
+
+        let block = reth_provider.get_block(BLOCK_NUMBER).await.unwrap();
+        assert!(block.is_some());
+
+        // the block is ahead of the local tip, so it is requested from the offline inner provider
+        let err = reth_provider.get_block(1_000_000u64).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "eth_getBlockByNumber is not supported without a network provider"
+        );
+        assert!(err.is_unsupported());
+
+        // unknown blocks have no receipts in the database either
+        let err = reth_provider.get_block_receipts(1_000_000u64).await.unwrap_err();
+        assert!(err.is_unsupported());
+
+        let stats = reth_provider.fallback_stats();
+        assert_eq!(stats.served_locally(), 1);
+        assert_eq!(stats.served_by_inner(), 2);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_shadow_compare() {
+        let divergences = Arc::new(Mutex::new(Vec::new()));
+        let reported = divergences.clone();
+        let reth_provider =
+            RethProvider::standalone(get_db_dir(), Handle::current(), DEV.clone()).unwrap();
+        let shadowed = ShadowMiddleware::new(
+            reth_provider,
+            Provider::new(OfflineClient),
+            ShadowConfig::new(0.5)
+                .reporter(move |divergence| reported.lock().unwrap().push(divergence.clone())),
+        );
+
+        for _ in 0..4 {
+            let block = shadowed.get_block(BLOCK_NUMBER).await.unwrap();
+            assert!(block.is_some());
+        }
+
+        // the offline reference rejects every shadowed request, so each one diverges once the
+        // comparisons running in the background are done
+        for _ in 0..100 {
+            if divergences.lock().unwrap().len() == 2 {
+                break
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let divergences = divergences.lock().unwrap();
+        assert_eq!(divergences.len(), 2);
+        assert_eq!(divergences[0].method, "get_block");
+        assert_eq!(divergences[0].differences[0].path, "$");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_detect_chain() {
+        let http_provider = spawn_http_provider(MAINNET_HTTP_URL).await.unwrap();
+        let reth_middleware =
+            RethMiddleware::with_detected_chain(http_provider, get_db_dir(), Handle::current())
+                .unwrap();
+
+        let chainid = reth_middleware.get_chainid().await.unwrap();
+
+        let expected_chainid: EthersU256 = DEV.clone().chain().id().into();
+
+        assert_eq!(expected_chainid, chainid);
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_get_block_number() {
@@ -285,6 +773,26 @@ mod tests {
         assert_eq!(expected_block_receipts, block_receipts);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_get_block_receipts_by_hash() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let block_hash: EthersH256 = BLOCK_HASH.parse().unwrap();
+        let block_receipts = reth_middleware.get_block_receipts_by_id(block_hash).await.unwrap();
+
+        let expected_block_receipts_path = get_testdata_dir().join("expected_block_receipts.json");
+        let expected_block_receipts: Vec<EthersTransactionReceipt> =
+            serde_json::from_str(&std::fs::read_to_string(expected_block_receipts_path).unwrap())
+                .unwrap();
+
+        assert_eq!(expected_block_receipts, block_receipts);
+
+        let err = reth_middleware.get_block_receipts_by_id(1_000_000u64).await.unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::BlockNotFound));
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_get_transaction() {
@@ -388,6 +896,57 @@ mod tests {
         assert_eq!(expected_logs, logs);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_logs_paginated() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let address: EthersAddress = WETH_ADDRESS.parse().unwrap();
+        let filter =
+            EthersFilter::new().from_block(0).to_block(BLOCK_NUMBER).address(vec![address]);
+
+        let logs: Vec<EthersLog> =
+            reth_middleware.logs_paginated(&filter, 1).try_collect().await.unwrap();
+
+        let expected_logs_path = get_testdata_dir().join("expected_logs.json");
+        let expected_logs: Vec<EthersLog> =
+            serde_json::from_str(&std::fs::read_to_string(expected_logs_path).unwrap()).unwrap();
+
+        assert_eq!(expected_logs, logs);
+        assert_eq!(reth_middleware.get_logs(&filter).await.unwrap(), logs);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_logs_paginated_splits_pages() {
+        let expected_logs_path = get_testdata_dir().join("expected_logs.json");
+        let expected_logs: Vec<EthersLog> =
+            serde_json::from_str(&std::fs::read_to_string(expected_logs_path).unwrap()).unwrap();
+
+        // the most logs any single block emits, so that every block fits in a response
+        let mut logs_per_block = std::collections::HashMap::<_, usize>::new();
+        for log in &expected_logs {
+            *logs_per_block.entry(log.block_number).or_default() += 1;
+        }
+        let max_logs_per_response = logs_per_block.into_values().max().unwrap();
+
+        let reth_provider =
+            RethMiddlewareBuilder::new(get_db_dir(), Handle::current(), DEV.clone())
+                .max_logs_per_response(max_logs_per_response)
+                .build_standalone()
+                .unwrap();
+
+        let address: EthersAddress = WETH_ADDRESS.parse().unwrap();
+        let filter =
+            EthersFilter::new().from_block(0).to_block(BLOCK_NUMBER).address(vec![address]);
+
+        // a single page over the whole range exceeds the limit and is split
+        let logs: Vec<EthersLog> =
+            reth_provider.logs_paginated(&filter, BLOCK_NUMBER + 1).try_collect().await.unwrap();
+        assert_eq!(expected_logs, logs);
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_trace_call() {
@@ -615,6 +1174,92 @@ mod tests {
         .unwrap();
 
         assert_eq!(expected_trace_transaction, trace_transaction_result);
+
+        let err =
+            reth_middleware.trace_transaction(EthersH256::repeat_byte(0x11)).await.unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::TransactionNotFound));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_trace_filter() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let weth: EthersAddress = WETH_ADDRESS.parse().unwrap();
+        let filter = EthersTraceFilter::default()
+            .from_block(BLOCK_NUMBER - 1)
+            .to_block(BLOCK_NUMBER)
+            .to_address(vec![weth]);
+
+        let trace_filter_result = reth_middleware.trace_filter(filter).await.unwrap();
+
+        // the WETH deployment in the previous block followed by the transfer in `BLOCK_NUMBER`
+        let mut expected_trace_filter: Vec<EthersTrace> = serde_json::from_str(
+            &std::fs::read_to_string(get_testdata_dir().join("expected_trace_transaction.json"))
+                .unwrap(),
+        )
+        .unwrap();
+        let expected_trace_block: Vec<EthersTrace> = serde_json::from_str(
+            &std::fs::read_to_string(get_testdata_dir().join("expected_trace_block.json")).unwrap(),
+        )
+        .unwrap();
+        expected_trace_filter.extend(expected_trace_block);
+
+        assert_eq!(expected_trace_filter, trace_filter_result);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_trace_filter_range() {
+        let reth_provider =
+            RethMiddlewareBuilder::new(get_db_dir(), Handle::current(), DEV.clone())
+                .max_trace_filter_blocks(2)
+                .build_standalone()
+                .unwrap();
+        let latest = reth_provider.get_block_number().await.unwrap().as_u64();
+
+        // tags are resolved against the database
+        let filter = EthersTraceFilter::default()
+            .from_block(EthersBlockNumber::Latest)
+            .to_block(EthersBlockNumber::Latest);
+        let traces = reth_provider.trace_filter(filter).await.unwrap();
+        assert_eq!(traces, reth_provider.trace_block(latest.into()).await.unwrap());
+
+        let filter =
+            EthersTraceFilter::default().from_block(BLOCK_NUMBER - 1).to_block(BLOCK_NUMBER);
+        assert!(reth_provider.trace_filter(filter).await.is_ok());
+
+        let filter =
+            EthersTraceFilter::default().from_block(BLOCK_NUMBER - 2).to_block(BLOCK_NUMBER);
+        assert!(matches!(
+            reth_provider.trace_filter(filter).await,
+            Err(RethMiddlewareError::InvalidParams(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_trace_filter_paginated() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let from: EthersAddress = WALLET_ADDRESS.parse().unwrap();
+        let filter = EthersTraceFilter::default()
+            .from_block(BLOCK_NUMBER - 1)
+            .to_block(BLOCK_NUMBER)
+            .from_address(vec![from])
+            .after(1)
+            .count(1);
+
+        let trace_filter_result = reth_middleware.trace_filter(filter).await.unwrap();
+
+        let expected_trace_block_path = get_testdata_dir().join("expected_trace_block.json");
+        let expected_trace_block: Vec<EthersTrace> =
+            serde_json::from_str(&std::fs::read_to_string(expected_trace_block_path).unwrap())
+                .unwrap();
+
+        assert_eq!(expected_trace_block, trace_filter_result);
     }
 
     #[tokio::test]
@@ -684,6 +1329,59 @@ mod tests {
         assert_eq!(expected_debug_trace_block_by_number, debug_trace_block_by_number_result);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_debug_trace_block_results() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        let block = reth_middleware.get_block(BLOCK_NUMBER).await.unwrap().unwrap();
+        let results = reth_middleware
+            .debug_trace_block_results(BLOCK_NUMBER, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(results.len(), block.transactions.len());
+
+        let traces = reth_middleware
+            .debug_trace_block_by_number(Some(BLOCK_NUMBER.into()), Default::default())
+            .await
+            .unwrap();
+        let results: Vec<EthersGethTrace> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, traces);
+
+        let err = reth_middleware
+            .debug_trace_block_results(1_000_000u64, Default::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::BlockNotFound));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_trace_errors() {
+        let reth_middleware =
+            spawn_reth_middleware(MAINNET_HTTP_URL, DEV.clone(), get_db_dir()).await;
+
+        // the latest block is traced if no block is given
+        let latest = reth_middleware.get_block(EthersBlockNumber::Latest).await.unwrap().unwrap();
+        let traces =
+            reth_middleware.debug_trace_block_by_number(None, Default::default()).await.unwrap();
+        assert_eq!(traces.len(), latest.transactions.len());
+
+        let err = reth_middleware
+            .trace_replay_block_transactions(1_000_000u64.into(), vec![EthersTraceType::Trace])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::BlockNotFound));
+
+        let err = reth_middleware.trace_block(1_000_000u64.into()).await.unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::BlockNotFound));
+
+        let transaction_hash: EthersH256 = WETH_DEPLOY_TX_HASH.parse().unwrap();
+        let err = reth_middleware.trace_get(transaction_hash, vec![1_000]).await.unwrap_err();
+        assert!(matches!(err, RethMiddlewareError::MissingTrace));
+    }
+
     #[tokio::test]
     #[serial]
     async fn debug_trace_call() {